@@ -1,27 +1,322 @@
-use std::iter;
+use std::env;
+use std::fs;
 
 fn main() {
-    println!("First solution: {}", find_fuel_min(|n| n));
-    println!("Second solution: {}", find_fuel_min(|n| n * (n + 1) / 2));
+    let crabs = input(include_str!("../../input/07.txt"));
+
+    let mut args = env::args().skip(1);
+    let mut cost: Option<Box<dyn Cost>> = None;
+    let mut weights = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--cost" => cost = Some(cost_by_name(&args.next().expect("missing cost name"))),
+            "--weights" => weights = Some(args.next().expect("missing weights file")),
+            err => panic!("unknown argument '{}'", err),
+        }
+    }
+
+    if cost.is_none() && weights.is_none() {
+        let linear = align(&crabs, &Linear).unwrap();
+        println!(
+            "First solution: {} (position {})",
+            linear.fuel, linear.position
+        );
+
+        let triangular = align(&crabs, &Triangular).unwrap();
+        println!(
+            "Second solution: {} (position {})",
+            triangular.fuel, triangular.position
+        );
+        return;
+    }
+
+    let mut cost = cost.unwrap_or_else(|| Box::new(Linear));
+    if let Some(path) = weights {
+        let weights = input(&fs::read_to_string(path).unwrap());
+        assert_eq!(
+            weights.len(),
+            crabs.len(),
+            "need exactly one weight per crab"
+        );
+        cost = Box::new(Weighted::new(weights, cost));
+    }
+    match align(&crabs, cost.as_ref()) {
+        Ok(alignment) => println!("Fuel: {} (position {})", alignment.fuel, alignment.position),
+        Err(err) => println!("{}", err),
+    }
+}
+
+/// The position all crabs move to and the fuel that costs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Alignment {
+    position: i64,
+    fuel: i128,
 }
 
-fn find_fuel_min(cost: fn(i64) -> i64) -> i64 {
-    let mut min = i64::MAX;
-    let crabs = input();
-    for i in 0..3000 {
-        let fuel = crabs
-            .iter()
-            .zip(iter::repeat(i))
-            .map(|(&a, b)| cost((a - b).abs()))
-            .sum();
-        min = min.min(fuel);
+/// The fuel for moving to this position is too large for an `i128`, so the best alignment
+/// can't be determined.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct FuelOverflow {
+    position: i64,
+}
+
+impl std::fmt::Display for FuelOverflow {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "fuel for position {} is too large", self.position)
+    }
+}
+
+/// Fuel that a crab needs to move some distance. The cost has to be convex and non-decreasing in
+/// the distance, otherwise `align` may miss the optimum.
+trait Cost {
+    /// Fuel for the crab with the given index to move `distance` steps, or `None` if that
+    /// overflows.
+    fn cost(&self, crab: usize, distance: i128) -> Option<i128>;
+
+    /// Directly compute the optimal alignment, if there is a closed form for it.
+    fn optimum(&self, _crabs: &[i64]) -> Option<Result<Alignment, FuelOverflow>> {
+        None
     }
-    min
 }
 
-fn input() -> Vec<i64> {
-    include_str!("../../input/07.txt")
-        .split(',')
-        .map(|n| n.parse().unwrap())
-        .collect()
+impl<F: Fn(i128) -> Option<i128>> Cost for F {
+    fn cost(&self, _crab: usize, distance: i128) -> Option<i128> {
+        self(distance)
+    }
+}
+
+/// Every step costs one fuel.
+struct Linear;
+
+impl Cost for Linear {
+    fn cost(&self, _crab: usize, distance: i128) -> Option<i128> {
+        Some(distance)
+    }
+
+    /// Linear cost is minimized at the median.
+    fn optimum(&self, crabs: &[i64]) -> Option<Result<Alignment, FuelOverflow>> {
+        let mut sorted = crabs.to_vec();
+        sorted.sort_unstable();
+        let position = sorted[sorted.len() / 2];
+        Some(alignment(crabs, position, self))
+    }
+}
+
+/// Every step costs one fuel more than the previous one.
+struct Triangular;
+
+impl Cost for Triangular {
+    fn cost(&self, _crab: usize, distance: i128) -> Option<i128> {
+        // One of the two factors is even, halve that one first so that the product fits.
+        if distance % 2 == 0 {
+            (distance / 2).checked_mul(distance + 1)
+        } else {
+            distance.checked_mul((distance + 1) / 2)
+        }
+    }
+
+    /// The real minimum lies within 1/2 of the mean, so the best integer position is one of the
+    /// few around it.
+    fn optimum(&self, crabs: &[i64]) -> Option<Result<Alignment, FuelOverflow>> {
+        around_mean(crabs, self)
+    }
+}
+
+/// Fuel grows with the square of the distance.
+struct Quadratic;
+
+impl Cost for Quadratic {
+    fn cost(&self, _crab: usize, distance: i128) -> Option<i128> {
+        distance.checked_mul(distance)
+    }
+
+    /// The sum of squares is minimized at the mean.
+    fn optimum(&self, crabs: &[i64]) -> Option<Result<Alignment, FuelOverflow>> {
+        around_mean(crabs, self)
+    }
+}
+
+/// Scales another cost by a weight per crab. Weights must not be negative.
+struct Weighted {
+    weights: Vec<i64>,
+    cost: Box<dyn Cost>,
+}
+
+impl Weighted {
+    /// Negative weights would make the total cost non-convex, so `align` could miss the optimum.
+    fn new(weights: Vec<i64>, cost: Box<dyn Cost>) -> Self {
+        assert!(
+            weights.iter().all(|&w| w >= 0),
+            "weights must not be negative"
+        );
+        Self { weights, cost }
+    }
+}
+
+impl Cost for Weighted {
+    fn cost(&self, crab: usize, distance: i128) -> Option<i128> {
+        (self.weights[crab] as i128).checked_mul(self.cost.cost(crab, distance)?)
+    }
+}
+
+/// Total fuel for moving all crabs to the given position, or `None` if that overflows.
+/// Distances are computed in `i128`, so crabs may be anywhere in the `i64` range.
+fn fuel(crabs: &[i64], position: i64, cost: &dyn Cost) -> Option<i128> {
+    crabs.iter().enumerate().try_fold(0i128, |sum, (i, &c)| {
+        let distance = (c as i128 - position as i128).abs();
+        sum.checked_add(cost.cost(i, distance)?)
+    })
+}
+
+fn alignment(crabs: &[i64], position: i64, cost: &dyn Cost) -> Result<Alignment, FuelOverflow> {
+    match fuel(crabs, position, cost) {
+        Some(fuel) => Ok(Alignment { position, fuel }),
+        None => Err(FuelOverflow { position }),
+    }
+}
+
+/// Find the cheapest position to align all crabs. Uses the closed form of the cost if it has one
+/// and otherwise searches for the point where the fuel stops decreasing, which is a minimum since
+/// the total cost is convex. Panics if there are no crabs.
+fn align(crabs: &[i64], cost: &dyn Cost) -> Result<Alignment, FuelOverflow> {
+    if let Some(alignment) = cost.optimum(crabs) {
+        return alignment;
+    }
+
+    // Moving past the outermost crabs only makes things worse.
+    let mut low = *crabs.iter().min().unwrap();
+    let mut high = *crabs.iter().max().unwrap();
+    while low < high {
+        // Written like this, neither the midpoint nor `mid + 1` can overflow.
+        let mid = (low as i128 + (high as i128 - low as i128) / 2) as i64;
+        // An overflowing fuel is bigger than any other, but two of them can't be compared.
+        let decreasing = match (fuel(crabs, mid, cost), fuel(crabs, mid + 1, cost)) {
+            (Some(here), Some(next)) => next < here,
+            (None, Some(_)) => true,
+            (Some(_), None) => false,
+            (None, None) => return Err(FuelOverflow { position: mid }),
+        };
+        if decreasing {
+            low = mid + 1;
+        } else {
+            high = mid;
+        }
+    }
+
+    alignment(crabs, low, cost)
+}
+
+/// Best alignment among the positions next to the mean.
+fn around_mean(crabs: &[i64], cost: &dyn Cost) -> Option<Result<Alignment, FuelOverflow>> {
+    // Sum in i128 so that crabs far out don't overflow.
+    let sum: i128 = crabs.iter().map(|&c| c as i128).sum();
+    let mean = sum.div_euclid(crabs.len() as i128);
+
+    // Positions outside of the i64 range are never better than the crab at the edge of it.
+    let positions = (mean - 1..=mean + 2).filter_map(|p| i64::try_from(p).ok());
+    let candidates: Vec<_> = positions.map(|p| alignment(crabs, p, cost)).collect();
+    // The best one fits if any of them does.
+    let best = candidates
+        .iter()
+        .filter_map(|a| a.ok())
+        .min_by_key(|a| a.fuel);
+    Some(best.ok_or_else(|| candidates[0].unwrap_err()))
+}
+
+fn cost_by_name(name: &str) -> Box<dyn Cost> {
+    match name {
+        "linear" => Box::new(Linear),
+        "triangular" => Box::new(Triangular),
+        "quadratic" => Box::new(Quadratic),
+        err => panic!("unknown cost '{}'", err),
+    }
+}
+
+fn input(s: &str) -> Vec<i64> {
+    s.trim().split(',').map(|n| n.parse().unwrap()).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{align, input, Alignment, FuelOverflow, Linear, Quadratic, Triangular, Weighted};
+
+    #[test]
+    fn example() {
+        let crabs = input(include_str!("../../input/07-test.txt"));
+        assert_eq!(
+            align(&crabs, &Linear).unwrap(),
+            Alignment {
+                position: 2,
+                fuel: 37
+            }
+        );
+        assert_eq!(
+            align(&crabs, &Triangular).unwrap(),
+            Alignment {
+                position: 5,
+                fuel: 168
+            }
+        );
+    }
+
+    #[test]
+    fn negative_positions() {
+        let crabs = [-5000, -4998, -4990];
+        assert_eq!(align(&crabs, &Linear).unwrap().position, -4998);
+        assert_eq!(align(&crabs, &Triangular).unwrap().position, -4996);
+    }
+
+    #[test]
+    fn search_matches_closed_form() {
+        let crabs = input(include_str!("../../input/07-test.txt"));
+        let search = |cost: fn(i128) -> Option<i128>| align(&crabs, &cost);
+        assert_eq!(align(&crabs, &Linear), search(Some));
+        assert_eq!(
+            align(&crabs, &Triangular),
+            search(|n| Some(n * (n + 1) / 2))
+        );
+        assert_eq!(align(&crabs, &Quadratic), search(|n| Some(n * n)));
+    }
+
+    #[test]
+    fn weighted() {
+        // The heavy crab at 10 pulls everyone over.
+        let crabs = [0, 1, 10];
+        let cost = Weighted::new(vec![1, 1, 5], Box::new(Linear));
+        assert_eq!(
+            align(&crabs, &cost),
+            Ok(Alignment {
+                position: 10,
+                fuel: 19
+            })
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "weights must not be negative")]
+    fn negative_weights() {
+        Weighted::new(vec![1, -1], Box::new(Linear));
+    }
+
+    #[test]
+    fn extremes() {
+        let crabs = [i64::MIN, i64::MAX];
+        assert_eq!(align(&crabs, &Linear).unwrap().fuel, u64::MAX as i128);
+        assert_eq!(
+            align(&crabs, &Triangular),
+            Ok(Alignment {
+                position: -1,
+                fuel: 1 << 126
+            })
+        );
+
+        // Two crabs at the far end push the squares out of range.
+        let crabs = [i64::MIN, i64::MAX, i64::MAX];
+        assert!(matches!(
+            align(&crabs, &Quadratic),
+            Err(FuelOverflow { .. })
+        ));
+        let search = |n: i128| n.checked_mul(n);
+        assert!(align(&crabs, &search).is_err());
+    }
 }
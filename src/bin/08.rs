@@ -1,32 +1,155 @@
+use std::env;
+use std::fmt::{self, Display};
+use std::fs;
+
 fn main() {
-    println!("First solution: {}", part1());
-    println!("Second solution: {}", part2());
+    let mut args = env::args().skip(1);
+    let mut font = None;
+    let mut file = None;
+    let mut show_wiring = false;
+    let mut style = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--font" => font = Some(Font::by_name(&args.next().expect("missing font"))),
+            "--input" => file = Some(args.next().expect("missing input file")),
+            "--wiring" => show_wiring = true,
+            "--render" => style = Some(Style::by_name(&args.next().expect("missing style"))),
+            err => panic!("unknown argument '{}'", err),
+        }
+    }
+
+    let text = match file {
+        Some(path) => fs::read_to_string(path).unwrap(),
+        None => include_str!("../../input/08.txt").to_string(),
+    };
+    let puzzles = input(&text);
+
+    if let Some(font) = font {
+        // Other fonts don't necessarily show numbers, so just decode every display.
+        for puzzle in &puzzles {
+            match puzzle.wiring(&font) {
+                Ok(wiring) if show_wiring => {
+                    println!(
+                        "{} ({})",
+                        wiring.decode(&font, &puzzle.challenge).unwrap(),
+                        wiring
+                    )
+                }
+                Ok(wiring) => println!("{}", wiring.decode(&font, &puzzle.challenge).unwrap()),
+                Err(err) => println!("{:?}", err),
+            }
+        }
+        return;
+    }
+
+    let font = Font::seven_segment();
+    if show_wiring {
+        for puzzle in &puzzles {
+            match puzzle.wiring(&font) {
+                Ok(wiring) => println!("{}", wiring),
+                Err(err) => println!("{:?}", err),
+            }
+        }
+    }
+
+    if let Some(style) = style {
+        // Scrambled patterns on the left, what they actually show on the right.
+        for puzzle in &puzzles {
+            let scrambled = render(&puzzle.challenge, style);
+            match puzzle.wiring(&font) {
+                Ok(wiring) => {
+                    let decoded: Vec<Digit> =
+                        puzzle.challenge.iter().map(|&d| wiring.apply(d)).collect();
+                    println!("{}", side_by_side(&scrambled, &render(&decoded, style)));
+                }
+                Err(err) => println!("{}{:?}\n", scrambled, err),
+            }
+        }
+    }
+
+    println!("First solution: {}", part1(&font, &puzzles));
+    println!("Second solution: {}", part2(&font, &puzzles));
 }
 
-fn part1() -> usize {
-    input()
+/// Count the challenge patterns that can be identified by their number of segments alone.
+fn part1(font: &Font, puzzles: &[Puzzle]) -> usize {
+    puzzles
         .iter()
         .flat_map(|p| &p.challenge)
-        .filter(|s| matches!(s.count_ones(), 2 | 4 | 3 | 7))
+        .filter(|s| font.with_segments(s.count_ones()).count() == 1)
         .count()
 }
 
-fn part2() -> usize {
-    input().iter().map(Puzzle::solve).sum()
+fn part2(font: &Font, puzzles: &[Puzzle]) -> usize {
+    puzzles
+        .iter()
+        .map(|p| p.solve(font).unwrap().parse::<usize>().unwrap())
+        .sum()
 }
 
-type Digit = u8;
+/// Set of segments or wires, segment a is the lowest bit.
+type Digit = u32;
 
-/// Parse chars a to g to bit flags.
+/// The standard font for the numbers 0 to 9.
+const SEVEN_SEGMENT: &str = "
+0 abcefg
+1 cf
+2 acdeg
+3 acdfg
+4 bcdf
+5 abdfg
+6 abdefg
+7 acf
+8 abcdefg
+9 abcdfg
+";
+
+/// Alphanumeric font. Segments a to f are the outer ones clockwise from the top, g and h are the
+/// left and right half of the middle bar, i to k the upper diagonals and center bar from
+/// left to right and l to n the lower ones.
+const FOURTEEN_SEGMENT: &str = "
+0 abcdefkl
+1 bck
+2 abdegh
+3 abcdh
+4 bcfgh
+5 adfgn
+6 acdefgh
+7 abc
+8 abcdefgh
+9 abcdfgh
+A abcefgh
+B abcdhjm
+C adef
+D abcdjm
+E adefgh
+F aefg
+G acdefh
+H bcefgh
+I adjm
+J bcde
+K efgkn
+L def
+M bcefik
+N bcefin
+O abcdef
+P abefgh
+Q abcdefn
+R abefghn
+S acdfgh
+T ajm
+U bcdef
+V efkl
+W bcefln
+X ikln
+Y ikm
+Z adkl
+";
+
+/// Parse chars a to z to bit flags.
 fn parse_char(s: char) -> Digit {
     match s {
-        'a' => 1,
-        'b' => 2,
-        'c' => 4,
-        'd' => 8,
-        'e' => 16,
-        'f' => 32,
-        'g' => 64,
+        'a'..='z' => 1 << (s as u32 - 'a' as u32),
         err => panic!("invalid digit '{}'", err),
     }
 }
@@ -35,6 +158,120 @@ fn parse_digit(s: &str) -> Digit {
     s.chars().fold(0, |a, b| a | parse_char(b))
 }
 
+/// Iterate over the indices of the set bits.
+fn bits(d: Digit) -> impl Iterator<Item = usize> {
+    (0..Digit::BITS as usize).filter(move |&i| d & (1 << i) != 0)
+}
+
+fn segment_name(i: usize) -> char {
+    (b'a' + i as u8) as char
+}
+
+/// The segments that every symbol of a display lights up.
+#[derive(Debug)]
+struct Font {
+    /// Number of segments (and wires) of the display.
+    segments: usize,
+    symbols: Vec<(char, Digit)>,
+}
+
+impl Font {
+    fn seven_segment() -> Self {
+        Self::from_str(SEVEN_SEGMENT)
+    }
+
+    fn fourteen_segment() -> Self {
+        Self::from_str(FOURTEEN_SEGMENT)
+    }
+
+    /// A built in font, or else a font file.
+    fn by_name(name: &str) -> Self {
+        match name {
+            "7" => Self::seven_segment(),
+            "14" => Self::fourteen_segment(),
+            path => Self::from_str(&fs::read_to_string(path).unwrap()),
+        }
+    }
+
+    /// Parse a font with one symbol per line, followed by its segments.
+    fn from_str(s: &str) -> Self {
+        let symbols: Vec<(char, Digit)> = s
+            .lines()
+            .filter_map(|l| l.trim().split_once(' '))
+            .map(|(symbol, segments)| {
+                let mut symbol = symbol.chars();
+                let c = symbol.next().unwrap();
+                assert_eq!(symbol.next(), None, "symbols must be a single char");
+                (c, parse_digit(segments.trim()))
+            })
+            .collect();
+        let all = symbols.iter().fold(0, |acc, &(_, d)| acc | d);
+        Self {
+            segments: (Digit::BITS - all.leading_zeros()) as usize,
+            symbols,
+        }
+    }
+
+    /// Set of all segments.
+    fn all(&self) -> Digit {
+        (1 << self.segments) - 1
+    }
+
+    fn with_segments(&self, n: u32) -> impl Iterator<Item = Digit> + '_ {
+        self.symbols
+            .iter()
+            .map(|&(_, d)| d)
+            .filter(move |d| d.count_ones() == n)
+    }
+
+    fn symbol(&self, segments: Digit) -> Option<char> {
+        self.symbols
+            .iter()
+            .find(|&&(_, d)| d == segments)
+            .map(|&(c, _)| c)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum SolveError {
+    /// No wiring explains all observed patterns.
+    Inconsistent,
+    /// More than one wiring explains all observed patterns.
+    Ambiguous,
+}
+
+/// Maps every wire to the segment it is connected to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Wiring(Vec<usize>);
+
+impl Wiring {
+    /// Translate a pattern of wires to the segments they light up.
+    fn apply(&self, d: Digit) -> Digit {
+        bits(d).fold(0, |acc, wire| acc | 1 << self.0[wire])
+    }
+
+    /// The symbol shown by a pattern of wires, if any.
+    fn decode_digit(&self, font: &Font, d: Digit) -> Option<char> {
+        font.symbol(self.apply(d))
+    }
+
+    fn decode(&self, font: &Font, digits: &[Digit]) -> Option<String> {
+        digits.iter().map(|&d| self.decode_digit(font, d)).collect()
+    }
+}
+
+impl Display for Wiring {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (wire, &segment) in self.0.iter().enumerate() {
+            if wire > 0 {
+                write!(f, " ")?;
+            }
+            write!(f, "{}->{}", segment_name(wire), segment_name(segment))?;
+        }
+        Ok(())
+    }
+}
+
 #[derive(Debug)]
 struct Puzzle {
     hints: Vec<Digit>,
@@ -42,57 +279,77 @@ struct Puzzle {
 }
 
 impl Puzzle {
-    fn solve(&self) -> usize {
-        // Easy numbers have unique numbers of segments
-        let one = assert_single(self.by_segments(2));
-        let four = assert_single(self.by_segments(4));
-        let seven = assert_single(self.by_segments(3));
-        let eight = assert_single(self.by_segments(7));
-
-        // Only three overlaps one
-        let three = self.by_segments(5).find(|&n| n & one == one).unwrap();
-
-        // Only nine overlaps four and seven
-        let nine = assert_single(
-            self.by_segments(6)
-                .filter(|&n| n & four == four && n & seven == seven),
-        );
-
-        // Find zero. It has 6 segments, is not nine and overlaps one.
-        let zero = assert_single(self.by_segments(6).filter(|&n| n != nine && n & one == one));
-
-        // six is the only 6 segment number that's not nine or zero
-        let six = assert_single(self.by_segments(6).filter(|&n| n != nine && n != zero));
+    fn solve(&self, font: &Font) -> Result<String, SolveError> {
+        let wiring = self.wiring(font)?;
+        // The wiring explains the challenge, so decoding can't fail.
+        Ok(wiring.decode(font, &self.challenge).unwrap())
+    }
 
-        // To get five, join one and six. That is a single segment that only five and three have, but we know three.
-        let five = assert_single(
-            self.by_segments(5)
-                .filter(|&n| n & one & six > 0 && n != three),
-        );
+    /// Find the one wiring that makes every hint and challenge pattern a valid symbol.
+    fn wiring(&self, font: &Font) -> Result<Wiring, SolveError> {
+        let patterns: Vec<Digit> = self.hints.iter().chain(&self.challenge).copied().collect();
+        if patterns.iter().any(|&p| p & !font.all() != 0) {
+            // There are more wires than segments.
+            return Err(SolveError::Inconsistent);
+        }
 
-        // Only two remains
-        let two = assert_single(self.by_segments(5).filter(|&n| n != five && n != three));
+        // Segments each wire may still be connected to.
+        let mut candidates = vec![font.all(); font.segments];
 
-        let nums = [zero, one, two, three, four, five, six, seven, eight, nine];
+        // A pattern with n wires shows one of the symbols with n segments. Its wires must be
+        // connected to segments that at least one of those symbols uses, and the other wires to
+        // segments that at least one of them doesn't use.
+        for &pattern in &patterns {
+            let any_lit = font
+                .with_segments(pattern.count_ones())
+                .fold(0, |acc, f| acc | f);
+            let all_lit = font
+                .with_segments(pattern.count_ones())
+                .fold(font.all(), |acc, f| acc & f);
+            for (wire, c) in candidates.iter_mut().enumerate() {
+                if pattern & (1 << wire) != 0 {
+                    *c &= any_lit;
+                } else {
+                    *c &= !all_lit;
+                }
+            }
+        }
 
-        // We can now decode the challenge
-        let mut result = 0;
-        for &d in &self.challenge {
-            result *= 10;
-            for (i, &n) in nums.iter().enumerate() {
-                if n == d {
-                    result += i;
+        // A wire with a single candidate segment takes that segment away from all others.
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for wire in 0..font.segments {
+                let c = candidates[wire];
+                if c.count_ones() != 1 {
+                    continue;
+                }
+                for (other, o) in candidates.iter_mut().enumerate() {
+                    if other != wire && *o & c != 0 {
+                        *o &= !c;
+                        changed = true;
+                    }
                 }
             }
         }
-        result
-    }
+        if candidates.contains(&0) {
+            return Err(SolveError::Inconsistent);
+        }
 
-    fn by_segments(&self, n: u32) -> impl Iterator<Item = Digit> {
-        self.hints
-            .clone()
-            .into_iter()
-            .filter(move |d| d.count_ones() == n)
+        // Check the remaining permutations.
+        let mut search = Search {
+            font,
+            candidates: &candidates,
+            patterns: &patterns,
+            assigned: vec![0; font.segments],
+            found: Vec::new(),
+        };
+        search.run(0, 0);
+        match &search.found[..] {
+            [] => Err(SolveError::Inconsistent),
+            [wiring] => Ok(wiring.clone()),
+            _ => Err(SolveError::Ambiguous),
+        }
     }
 
     fn from_str(s: &str) -> Self {
@@ -104,15 +361,196 @@ impl Puzzle {
     }
 }
 
-fn assert_single(mut it: impl Iterator<Item = Digit>) -> Digit {
-    let result = it.next().unwrap();
-    assert_eq!(it.next(), None);
+/// Backtracking search over the wire permutations that are left after constraint propagation.
+struct Search<'a> {
+    font: &'a Font,
+    candidates: &'a [Digit],
+    patterns: &'a [Digit],
+    assigned: Vec<usize>,
+    found: Vec<Wiring>,
+}
+
+impl Search<'_> {
+    /// Assign the remaining wires starting at `wire` and collect wirings that explain all
+    /// patterns. Stops after the second one, since that is enough to know that the puzzle is
+    /// ambiguous.
+    fn run(&mut self, wire: usize, used: Digit) {
+        if self.found.len() > 1 || !self.fits(wire) {
+            return;
+        }
+        if wire == self.font.segments {
+            self.found.push(Wiring(self.assigned.clone()));
+            return;
+        }
+        for segment in bits(self.candidates[wire] & !used) {
+            self.assigned[wire] = segment;
+            self.run(wire + 1, used | 1 << segment);
+        }
+    }
+
+    /// Whether every pattern can still become a symbol, given the wires below `wire`.
+    fn fits(&self, wire: usize) -> bool {
+        let known: Digit = (1 << wire) - 1;
+        let map = |d: Digit| bits(d & known).fold(0, |acc: Digit, w| acc | 1 << self.assigned[w]);
+        self.patterns.iter().all(|&p| {
+            let lit = map(p);
+            let dark = map(!p);
+            self.font
+                .with_segments(p.count_ones())
+                .any(|f| f & lit == lit && f & dark == 0)
+        })
+    }
+}
+
+/// Size of the ASCII art for seven segment digits.
+#[derive(Debug, Clone, Copy)]
+enum Style {
+    /// 3×3 characters.
+    Small,
+    /// 5×7 characters.
+    Large,
+}
+
+impl Style {
+    fn by_name(name: &str) -> Self {
+        match name {
+            "small" => Self::Small,
+            "large" => Self::Large,
+            err => panic!("unknown style '{}'", err),
+        }
+    }
+
+    /// Where each segment is drawn.
+    fn template(self) -> &'static [&'static str] {
+        match self {
+            Self::Small => &[" a ", "bdc", "egf"],
+            Self::Large => &[
+                " aaa ", "b   c", "b   c", " ddd ", "e   f", "e   f", " ggg ",
+            ],
+        }
+    }
+
+    fn stroke(self, segment: char) -> char {
+        match (self, segment) {
+            (_, 'b' | 'c' | 'e' | 'f') => '|',
+            (Self::Small, _) => '_',
+            (Self::Large, _) => '-',
+        }
+    }
+}
+
+/// Draw a row of seven segment digits as ASCII art, separated by a space.
+fn render(digits: &[Digit], style: Style) -> String {
+    let mut result = String::new();
+    for row in style.template() {
+        for (i, &d) in digits.iter().enumerate() {
+            if i > 0 {
+                result.push(' ');
+            }
+            for c in row.chars() {
+                if c != ' ' && d & parse_char(c) != 0 {
+                    result.push(style.stroke(c));
+                } else {
+                    result.push(' ');
+                }
+            }
+        }
+        result.push('\n');
+    }
     result
 }
 
-fn input() -> Vec<Puzzle> {
-    include_str!("../../input/08.txt")
-        .lines()
-        .map(Puzzle::from_str)
+/// Put two renderings of the same height next to each other.
+fn side_by_side(left: &str, right: &str) -> String {
+    left.lines()
+        .zip(right.lines())
+        .map(|(l, r)| format!("{}   {}\n", l, r))
         .collect()
 }
+
+fn input(s: &str) -> Vec<Puzzle> {
+    s.lines().map(Puzzle::from_str).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        input, parse_digit, part1, part2, render, Font, Puzzle, SolveError, Style, FOURTEEN_SEGMENT,
+    };
+
+    #[test]
+    fn example() {
+        let font = Font::seven_segment();
+        let puzzles = input(include_str!("../../input/08-test.txt"));
+        assert_eq!(part1(&font, &puzzles), 26);
+        assert_eq!(part2(&font, &puzzles), 61229);
+    }
+
+    #[test]
+    fn wiring() {
+        let font = Font::seven_segment();
+        let puzzle = Puzzle::from_str(
+            "acedgfb cdfbe gcdfa fbcad dab cefabd cdfgeb eafb cagedb ab | cdfeb fcadb cdfeb cdbaf",
+        );
+        let wiring = puzzle.wiring(&font).unwrap();
+        assert_eq!(wiring.to_string(), "a->c b->f c->g d->a e->b f->d g->e");
+        assert_eq!(puzzle.solve(&font), Ok("5353".to_string()));
+    }
+
+    #[test]
+    fn unsolvable() {
+        let font = Font::seven_segment();
+        assert_eq!(
+            Puzzle::from_str("ab | ab").wiring(&font),
+            Err(SolveError::Ambiguous)
+        );
+        // Two different patterns can't both be a one.
+        assert_eq!(
+            Puzzle::from_str("ab cd | ab").wiring(&font),
+            Err(SolveError::Inconsistent)
+        );
+    }
+
+    #[test]
+    fn fourteen_segments() {
+        let font = Font::fourteen_segment();
+        assert_eq!(font.segments, 14);
+
+        // Scramble every symbol of the font with a fixed wiring and solve it again.
+        let scramble = |segments: &str| -> String {
+            segments
+                .chars()
+                .map(|c| (b'a' + (c as u8 - b'a' + 5) % 14) as char)
+                .collect()
+        };
+        let hints: Vec<String> = FOURTEEN_SEGMENT
+            .lines()
+            .filter_map(|l| l.split_once(' '))
+            .map(|(_, segments)| scramble(segments))
+            .collect();
+        let line = format!(
+            "{} | {} {} {}",
+            hints.join(" "),
+            scramble("bcefik"),
+            scramble("abefghn"),
+            scramble("acdfgh")
+        );
+        let puzzle = Puzzle::from_str(&line);
+        assert_eq!(puzzle.solve(&font), Ok("MRS".to_string()));
+        assert_eq!(
+            font.symbol(parse_digit("abcdefkl")),
+            Some('0'),
+            "zero has a slash"
+        );
+    }
+
+    #[test]
+    fn ascii_art() {
+        let digits = [parse_digit("abcdefg"), parse_digit("cf")];
+        assert_eq!(render(&digits, Style::Small), " _     \n|_|   |\n|_|   |\n");
+        assert_eq!(
+            render(&digits[..1], Style::Large),
+            " --- \n|   |\n|   |\n --- \n|   |\n|   |\n --- \n"
+        );
+    }
+}
@@ -1,10 +1,173 @@
+use std::env;
+use std::fmt::{self, Display};
+use std::fs;
+
 fn main() {
-    println!("First solution: {}", input().risk());
-    println!("Second solution: {}", input().part2());
+    let mut rules = BasinRules::default();
+    let mut show_basins = false;
+    let mut color = false;
+    let mut ppm = None;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--basins" => show_basins = true,
+            "--color" => color = true,
+            "--ppm" => ppm = Some(args.next().expect("missing image path")),
+            "--wall-threshold" => {
+                rules.walls = Walls::AtLeast(args.next().unwrap().parse().unwrap())
+            }
+            "--wall-heights" => {
+                rules.walls = Walls::Heights(
+                    args.next()
+                        .unwrap()
+                        .split(',')
+                        .map(|h| h.parse().unwrap())
+                        .collect(),
+                )
+            }
+            "--diagonal" => rules.connectivity = Connectivity::Eight,
+            "--drainage" => rules.method = Method::Drainage,
+            err => panic!("unknown argument '{}'", err),
+        }
+    }
+
+    let field = input(include_str!("../../input/09.txt"));
+    let basins = field.basins(&rules);
+    if show_basins {
+        for (id, basin) in basins.basins.iter().enumerate() {
+            println!(
+                "Basin {}: low point {:?}, size {}",
+                id,
+                basin.low_point,
+                basin.size()
+            );
+        }
+        println!("{}", basins);
+    }
+    if color {
+        print!("{}", field.ansi(&basins));
+    }
+    if let Some(path) = ppm {
+        fs::write(path, field.ppm(&basins, 4)).unwrap();
+    }
+    println!("First solution: {}", field.risk());
+    println!("Second solution: {}", basins.part2());
 }
 
+/// Row and column.
+type Position = (usize, usize);
+
 struct Field(Vec<Vec<u32>>);
 
+/// Heights that separate basins and don't belong to any.
+#[derive(Debug)]
+enum Walls {
+    AtLeast(u32),
+    Heights(Vec<u32>),
+}
+
+impl Walls {
+    fn contains(&self, height: u32) -> bool {
+        match self {
+            Walls::AtLeast(n) => height >= *n,
+            Walls::Heights(heights) => heights.contains(&height),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Connectivity {
+    /// Only horizontal and vertical neighbors.
+    Four,
+    /// Diagonal neighbors too.
+    Eight,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Method {
+    /// A basin is everything that is connected without crossing a wall.
+    Flood,
+    /// A basin is everything that flows down to the same low point, always moving to the lowest
+    /// neighbor. Cells without a lower neighbor are low points, so a flat area is made up of
+    /// many basins.
+    Drainage,
+}
+
+#[derive(Debug)]
+struct BasinRules {
+    walls: Walls,
+    connectivity: Connectivity,
+    method: Method,
+}
+
+impl Default for BasinRules {
+    /// The rules from the puzzle.
+    fn default() -> Self {
+        Self {
+            walls: Walls::AtLeast(9),
+            connectivity: Connectivity::Four,
+            method: Method::Flood,
+        }
+    }
+}
+
+/// A region of the field.
+#[derive(Debug)]
+struct Basin {
+    low_point: Position,
+    cells: Vec<Position>,
+}
+
+impl Basin {
+    fn size(&self) -> usize {
+        self.cells.len()
+    }
+}
+
+/// Result of labeling all basins of a field.
+#[derive(Debug)]
+struct Basins {
+    /// Index into `basins` for every cell, or `None` for walls.
+    map: Vec<Vec<Option<usize>>>,
+    basins: Vec<Basin>,
+}
+
+impl Basins {
+    fn is_low_point(&self, row: usize, col: usize) -> bool {
+        match self.map[row][col] {
+            Some(id) => self.basins[id].low_point == (row, col),
+            None => false,
+        }
+    }
+
+    /// Sizes of all basins, largest first.
+    fn sizes(&self) -> Vec<usize> {
+        let mut sizes: Vec<usize> = self.basins.iter().map(Basin::size).collect();
+        sizes.sort_unstable_by(|a, b| b.cmp(a));
+        sizes
+    }
+
+    fn part2(&self) -> usize {
+        self.sizes().into_iter().take(3).product()
+    }
+}
+
+impl Display for Basins {
+    /// Draw every cell as the last digit of its basin ID, walls as dots.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for row in &self.map {
+            for cell in row {
+                match cell {
+                    Some(id) => write!(f, "{}", id % 10)?,
+                    None => write!(f, ".")?,
+                }
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
 impl Field {
     fn risk(&self) -> u32 {
         let mut risk = 0;
@@ -20,73 +183,280 @@ impl Field {
     }
 
     fn neighbor_values(&self, row: usize, col: usize) -> Vec<u32> {
-        self.neighbor_positions(row, col)
+        self.neighbor_positions(row, col, Connectivity::Four)
             .into_iter()
             .map(|(row, col)| self.0[row][col])
             .collect()
     }
 
-    fn neighbor_positions(&self, row: usize, col: usize) -> Vec<(usize, usize)> {
-        let mut result = Vec::new();
-        if row > 0 {
-            result.push((row - 1, col));
-        }
-        if row < self.0.len() - 1 {
-            result.push((row + 1, col));
-        }
-        if col > 0 {
-            result.push((row, col - 1));
-        }
-        if col < self.0[0].len() - 1 {
-            result.push((row, col + 1));
+    fn neighbor_positions(
+        &self,
+        row: usize,
+        col: usize,
+        connectivity: Connectivity,
+    ) -> Vec<(usize, usize)> {
+        let offsets: &[(isize, isize)] = match connectivity {
+            Connectivity::Four => &[(-1, 0), (1, 0), (0, -1), (0, 1)],
+            Connectivity::Eight => &[
+                (-1, 0),
+                (1, 0),
+                (0, -1),
+                (0, 1),
+                (-1, -1),
+                (-1, 1),
+                (1, -1),
+                (1, 1),
+            ],
+        };
+        offsets
+            .iter()
+            .filter_map(|&(dr, dc)| {
+                let row = row.checked_add_signed(dr)?;
+                let col = col.checked_add_signed(dc)?;
+                (row < self.0.len() && col < self.0[0].len()).then_some((row, col))
+            })
+            .collect()
+    }
+
+    /// Label every cell with the basin it belongs to.
+    fn basins(&self, rules: &BasinRules) -> Basins {
+        let (map, count) = match rules.method {
+            Method::Flood => self.flood(rules),
+            Method::Drainage => self.drainage(rules),
+        };
+
+        let mut cells = vec![Vec::new(); count];
+        for (row, line) in map.iter().enumerate() {
+            for (col, id) in line.iter().enumerate() {
+                if let Some(id) = *id {
+                    cells[id].push((row, col));
+                }
+            }
         }
-        result
+        let basins = cells
+            .into_iter()
+            .map(|cells| Basin {
+                low_point: *cells.iter().min_by_key(|&&(r, c)| self.0[r][c]).unwrap(),
+                cells,
+            })
+            .collect();
+
+        Basins { map, basins }
     }
 
-    fn part2(&mut self) -> u32 {
-        let mut basin_sizes = Vec::new();
+    /// Flood fill all cells that are connected. Every cell is filled exactly once. Returns the
+    /// basin map and the number of basins.
+    fn flood(&self, rules: &BasinRules) -> (Vec<Vec<Option<usize>>>, usize) {
+        let mut map = vec![vec![None; self.0[0].len()]; self.0.len()];
+        let mut count = 0;
+
+        for row in 0..self.0.len() {
+            for col in 0..self.0[0].len() {
+                if rules.walls.contains(self.0[row][col]) || map[row][col].is_some() {
+                    continue;
+                }
 
-        loop {
-            let mut fill_start = None;
-            'search: for row in 0..self.0.len() {
-                for col in 0..self.0[0].len() {
-                    if self.0[row][col] < 9 {
-                        fill_start = Some((row, col));
-                        break 'search;
+                let id = count;
+                count += 1;
+                let mut fill_stack = vec![(row, col)];
+                map[row][col] = Some(id);
+                while let Some((row, col)) = fill_stack.pop() {
+                    for (nr, nc) in self.neighbor_positions(row, col, rules.connectivity) {
+                        if !rules.walls.contains(self.0[nr][nc]) && map[nr][nc].is_none() {
+                            map[nr][nc] = Some(id);
+                            fill_stack.push((nr, nc));
+                        }
                     }
                 }
             }
+        }
+
+        (map, count)
+    }
 
-            let start = match fill_start {
-                Some(f) => f,
-                None => break,
-            };
+    /// Follow the steepest descent from every cell and label it with the low point it ends up
+    /// in. Returns the basin map and the number of basins.
+    fn drainage(&self, rules: &BasinRules) -> (Vec<Vec<Option<usize>>>, usize) {
+        let mut map = vec![vec![None; self.0[0].len()]; self.0.len()];
+        let mut count = 0;
 
-            let mut fill_stack = vec![start];
-            let mut size = 0;
-            while let Some((row, col)) = fill_stack.pop() {
-                if self.0[row][col] >= 9 {
+        for row in 0..self.0.len() {
+            for col in 0..self.0[0].len() {
+                if rules.walls.contains(self.0[row][col]) {
                     continue;
                 }
-                size += 1;
-                self.0[row][col] = 9; // Mark field as processed
-                for (nr, nc) in self.neighbor_positions(row, col) {
-                    fill_stack.push((nr, nc));
+
+                // Walk down until we reach a labeled cell or a low point.
+                let mut path = Vec::new();
+                let mut current = (row, col);
+                let id = loop {
+                    if let Some(id) = map[current.0][current.1] {
+                        break id;
+                    }
+                    path.push(current);
+                    match self.downhill(current, rules) {
+                        Some(next) => current = next,
+                        None => {
+                            count += 1;
+                            break count - 1;
+                        }
+                    }
+                };
+                for (r, c) in path {
+                    map[r][c] = Some(id);
                 }
             }
-            basin_sizes.push(size);
         }
 
-        basin_sizes.sort_unstable();
-        basin_sizes.into_iter().rev().take(3).product()
+        (map, count)
+    }
+
+    /// The lowest neighbor that is lower than the given cell. The first one wins a tie.
+    fn downhill(&self, (row, col): Position, rules: &BasinRules) -> Option<Position> {
+        let height = self.0[row][col];
+        self.neighbor_positions(row, col, rules.connectivity)
+            .into_iter()
+            .filter(|&(r, c)| self.0[r][c] < height && !rules.walls.contains(self.0[r][c]))
+            .min_by_key(|&(r, c)| self.0[r][c])
     }
 }
 
-fn input() -> Field {
+impl Field {
+    /// Draw the heights on a background with a different color for every basin. Low points are
+    /// drawn as stars.
+    fn ansi(&self, basins: &Basins) -> String {
+        let mut result = String::new();
+        for (row, line) in self.0.iter().enumerate() {
+            for (col, height) in line.iter().enumerate() {
+                let (r, g, b) = basins.map[row][col].map_or(WALL, basin_color);
+                result += &format!("\x1b[48;2;{};{};{}m\x1b[30m", r, g, b);
+                if basins.is_low_point(row, col) {
+                    result.push('*');
+                } else {
+                    result += &height.to_string();
+                }
+            }
+            result += "\x1b[0m\n";
+        }
+        result
+    }
+
+    /// The basin map as an image, blown up by `scale`. Each basin keeps the hue it has in the
+    /// terminal and fades towards its rim, walls are dark and low points white.
+    fn ppm(&self, basins: &Basins, scale: usize) -> Vec<u8> {
+        let width = self.0[0].len() * scale;
+        let height = self.0.len() * scale;
+        let mut result = format!("P6\n{} {}\n255\n", width, height).into_bytes();
+        for y in 0..height {
+            for x in 0..width {
+                let (row, col) = (y / scale, x / scale);
+                let (r, g, b) = match basins.map[row][col] {
+                    _ if basins.is_low_point(row, col) => (255, 255, 255),
+                    Some(id) => {
+                        let shade = |c: u8| (c as u32 * (12 - self.0[row][col]) / 12) as u8;
+                        let (r, g, b) = basin_color(id);
+                        (shade(r), shade(g), shade(b))
+                    }
+                    None => WALL,
+                };
+                result.extend([r, g, b]);
+            }
+        }
+        result
+    }
+}
+
+/// Color of cells that don't belong to any basin.
+const WALL: (u8, u8, u8) = (40, 40, 40);
+
+/// Spread hues of basins around the color wheel, so that neighbors are unlikely to look alike.
+fn basin_color(id: usize) -> (u8, u8, u8) {
+    let hue = (id as f64 * 0.618_033_988_75).fract() * 6.0;
+    let (s, v) = (0.6, 0.95);
+    let f = hue.fract();
+    let (p, q, t) = (v * (1.0 - s), v * (1.0 - s * f), v * (1.0 - s * (1.0 - f)));
+    let (r, g, b) = match hue as u32 {
+        0 => (v, t, p),
+        1 => (q, v, p),
+        2 => (p, v, t),
+        3 => (p, q, v),
+        4 => (t, p, v),
+        _ => (v, p, q),
+    };
+    let byte = |c: f64| (c * 255.0).round() as u8;
+    (byte(r), byte(g), byte(b))
+}
+
+fn input(s: &str) -> Field {
     Field(
-        include_str!("../../input/09.txt")
-            .lines()
+        s.lines()
             .map(|l| l.chars().map(|c| c.to_digit(10).unwrap()).collect())
             .collect(),
     )
 }
+
+#[cfg(test)]
+mod test {
+    use crate::{input, BasinRules, Connectivity, Method, Walls, WALL};
+
+    #[test]
+    fn example() {
+        let field = input(include_str!("../../input/09-test.txt"));
+        assert_eq!(field.risk(), 15);
+
+        let basins = field.basins(&BasinRules::default());
+        assert_eq!(basins.sizes(), vec![14, 9, 9, 3]);
+        assert_eq!(basins.part2(), 1134);
+
+        // The field is still intact.
+        assert_eq!(field.risk(), 15);
+
+        let top_left = &basins.basins[basins.map[0][0].unwrap()];
+        assert_eq!(top_left.low_point, (0, 1));
+        assert_eq!(top_left.size(), 3);
+        assert_eq!(basins.map[0][2], None);
+    }
+
+    #[test]
+    fn rules() {
+        let field = input("19\n91");
+        let mut rules = BasinRules::default();
+        assert_eq!(field.basins(&rules).sizes(), vec![1, 1]);
+        rules.connectivity = Connectivity::Eight;
+        assert_eq!(field.basins(&rules).sizes(), vec![2]);
+        rules.walls = Walls::Heights(vec![1]);
+        assert_eq!(field.basins(&rules).sizes(), vec![2]);
+        assert_eq!(field.basins(&rules).basins[0].low_point, (0, 1));
+
+        let field = input("0123210");
+        rules = BasinRules {
+            method: Method::Drainage,
+            ..BasinRules::default()
+        };
+        assert_eq!(field.basins(&rules).sizes(), vec![4, 3]);
+    }
+
+    #[test]
+    fn render() {
+        let field = input("19\n99");
+        let basins = field.basins(&BasinRules::default());
+
+        let ppm = field.ppm(&basins, 2);
+        let header = b"P6\n4 4\n255\n";
+        assert!(ppm.starts_with(header));
+        assert_eq!(ppm.len(), header.len() + 4 * 4 * 3);
+        let pixel = |x: usize, y: usize| {
+            let i = header.len() + (y * 4 + x) * 3;
+            (ppm[i], ppm[i + 1], ppm[i + 2])
+        };
+        assert_eq!(pixel(1, 1), (255, 255, 255));
+        assert_eq!(pixel(2, 0), WALL);
+        assert_eq!(pixel(3, 3), WALL);
+
+        let ansi = field.ansi(&basins);
+        // Every cell sets its colors and then prints a single character.
+        assert_eq!(ansi.matches("\x1b[30m*").count(), 1);
+        assert_eq!(ansi.matches("\x1b[30m9").count(), 3);
+        assert_eq!(ansi.lines().count(), 2);
+    }
+}
@@ -1,97 +1,553 @@
-use std::collections::VecDeque;
-
-type Line = VecDeque<char>;
+use std::env;
+use std::fmt::{self, Display};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read};
 
 fn main() {
-    let (score, incomplete_lines) = part1();
-    println!("First solution: {}", score);
-    println!("Second solution: {}", part2(incomplete_lines));
+    let mut args = env::args().skip(1);
+    let mut language = Language::aoc();
+    let mut file = None;
+    let mut diagnostics = false;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--diagnostics" => diagnostics = true,
+            "--language" => language = Language::by_name(&args.next().expect("missing language")),
+            "--input" => file = Some(args.next().expect("missing input file")),
+            err => panic!("unknown argument '{}'", err),
+        }
+    }
+
+    if let Some(path) = file {
+        // Lint a file of our own. These can be huge, so don't load them all at once.
+        check_stream(&language, File::open(path).unwrap(), |line, status| {
+            println!("Line {}: {}", line + 1, status)
+        })
+        .unwrap();
+        return;
+    }
+
+    let input = include_str!("../../input/10.txt");
+    if diagnostics {
+        print_diagnostics(&language, input);
+    }
+
+    let (corrupted, incomplete) = solve(&language, input);
+    println!("First solution: {}", corrupted);
+    println!("Second solution: {}", incomplete);
 }
 
-fn part1() -> (usize, Vec<Line>) {
-    let mut score = 0;
+fn print_diagnostics(language: &Language, input: &str) {
+    for (i, line) in input.lines().enumerate() {
+        println!("Line {}: {}", i + 1, language.check(line));
+    }
+}
 
-    let mut input = input();
-    input.retain(|f| {
-        let mut stack = Vec::new();
-        let mut line = f.clone();
-        while let Some(next) = line.pop_front() {
-            if is_open(next) {
-                stack.push(next);
-            } else {
-                let popped = stack.pop().unwrap();
-                if !is_pair(popped, next) {
-                    // This line is corrupted.
-                    score += char_score_invalid(next);
-                    return false;
-                }
+/// Outcome of checking a line. Columns are indices of chars in the line.
+#[derive(Debug, PartialEq, Eq)]
+enum Status {
+    Valid,
+    /// A closer doesn't match the last opener.
+    Corrupted {
+        column: usize,
+        expected: String,
+        found: String,
+        opened_at: usize,
+    },
+    /// A closer appears while nothing is open.
+    UnexpectedCloser {
+        column: usize,
+        found: String,
+    },
+    /// The line ends before everything is closed. Lists the missing closers in order.
+    Incomplete {
+        missing: Vec<String>,
+    },
+}
+
+impl Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Status::Valid => write!(f, "valid"),
+            Status::Corrupted {
+                column,
+                expected,
+                found,
+                opened_at,
+            } => write!(
+                f,
+                "expected '{}' to close column {}, but found '{}' at column {}",
+                expected, opened_at, found, column
+            ),
+            Status::UnexpectedCloser { column, found } => {
+                write!(f, "unexpected '{}' at column {}", found, column)
+            }
+            Status::Incomplete { missing } => {
+                write!(f, "incomplete, missing '{}'", missing.concat())
             }
         }
-        true
-    });
+    }
+}
+
+/// Delimiters that have to be balanced and what they score.
+#[derive(Debug)]
+struct Pair {
+    open: String,
+    close: String,
+    /// Score when the closer is found in the wrong place.
+    invalid_score: usize,
+    /// Score when the closer is missing at the end of a line.
+    incomplete_score: usize,
+    /// Whether string literals are recognized between the delimiters. Outside of all pairs
+    /// they never are, so that quotes in plain text don't count.
+    strings: bool,
+}
 
-    (score, input)
+impl Pair {
+    fn new(open: &str, close: &str, invalid_score: usize, incomplete_score: usize) -> Self {
+        Self {
+            open: open.to_string(),
+            close: close.to_string(),
+            invalid_score,
+            incomplete_score,
+            strings: true,
+        }
+    }
 }
 
-fn part2(lines: Vec<Line>) -> usize {
-    let mut scores = Vec::new();
+/// Everything that is needed to check a line.
+#[derive(Debug)]
+struct Language {
+    pairs: Vec<Pair>,
+    /// Chars that start and end string literals, which are not checked.
+    quotes: Vec<char>,
+    /// Char that makes the next char in a string literal lose its meaning.
+    escape: Option<char>,
+}
 
-    for mut line in lines {
-        let mut stack = Vec::new();
-        while let Some(next) = line.pop_front() {
-            if is_open(next) {
-                stack.push(next);
-            } else {
-                stack.pop();
+#[derive(Clone, Copy)]
+enum Token {
+    Open(usize),
+    Close(usize),
+}
+
+impl Language {
+    /// The brackets from the puzzle.
+    fn aoc() -> Self {
+        Self {
+            pairs: vec![
+                Pair::new("(", ")", 3, 1),
+                Pair::new("[", "]", 57, 2),
+                Pair::new("{", "}", 1197, 3),
+                Pair::new("<", ">", 25137, 4),
+            ],
+            quotes: Vec::new(),
+            escape: None,
+        }
+    }
+
+    /// Jinja style templates. Every problem scores one. Comments are free text, so they may
+    /// contain lone quotes.
+    fn template() -> Self {
+        let pairs = [
+            ("{%", "%}", true),
+            ("{{", "}}", true),
+            ("{#", "#}", false),
+            ("(", ")", true),
+            ("[", "]", true),
+            ("{", "}", true),
+        ];
+        Self {
+            pairs: pairs
+                .iter()
+                .map(|&(open, close, strings)| Pair {
+                    strings,
+                    ..Pair::new(open, close, 1, 1)
+                })
+                .collect(),
+            quotes: vec!['"', '\''],
+            escape: Some('\\'),
+        }
+    }
+
+    fn by_name(name: &str) -> Self {
+        match name {
+            "aoc" => Self::aoc(),
+            "template" => Self::template(),
+            err => panic!("unknown language '{}'", err),
+        }
+    }
+
+    /// The delimiter at the start of `s` and its length in bytes. That's the longest one, unless
+    /// a shorter one closes the innermost open pair `top`. So `}}` is read as two `}` when
+    /// there is a `{` to close.
+    fn token(&self, s: &[u8], top: Option<usize>) -> Option<(Token, usize)> {
+        let mut best: Option<(Token, usize)> = None;
+        let mut closes_top = None;
+        for (i, pair) in self.pairs.iter().enumerate() {
+            for (delimiter, token) in [(&pair.open, Token::Open(i)), (&pair.close, Token::Close(i))]
+            {
+                let len = delimiter.len();
+                if !s.starts_with(delimiter.as_bytes()) {
+                    continue;
+                }
+                if matches!(token, Token::Close(_)) && top == Some(i) {
+                    closes_top = Some((token, len));
+                }
+                if best.as_ref().is_none_or(|b| len > b.1) {
+                    best = Some((token, len));
+                }
+            }
+        }
+        closes_top.or(best)
+    }
+
+    /// Whether some delimiter is longer than `s` and starts with it.
+    fn could_grow(&self, s: &[u8]) -> bool {
+        self.pairs
+            .iter()
+            .flat_map(|p| [&p.open, &p.close])
+            .any(|d| d.len() > s.len() && d.as_bytes().starts_with(s))
+    }
+
+    fn pair_by_close(&self, close: &str) -> Option<&Pair> {
+        self.pairs.iter().find(|p| p.close == close)
+    }
+
+    fn check(&self, line: &str) -> Status {
+        let mut checker = Checker::new(self);
+        for &b in line.as_bytes() {
+            if let Some(status) = checker.push(b) {
+                return status;
             }
         }
+        // Nothing has been reported yet, so the line ends with a status.
+        checker.end_line().unwrap()
+    }
 
-        let mut score = 0;
-        while let Some(next) = stack.pop() {
-            score *= 5;
-            score += char_score_incomplete(next);
+    /// Score for the first part, if the line is corrupted.
+    fn invalid_score(&self, status: &Status) -> Option<usize> {
+        match status {
+            Status::Corrupted { found, .. } | Status::UnexpectedCloser { found, .. } => {
+                Some(self.pair_by_close(found)?.invalid_score)
+            }
+            _ => None,
         }
-        scores.push(score);
     }
 
-    scores.sort_unstable();
-    scores[scores.len() / 2]
+    /// Score for the second part, if the line is incomplete.
+    fn incomplete_score(&self, status: &Status) -> Option<usize> {
+        match status {
+            Status::Incomplete { missing } => Some(missing.iter().fold(0, |score, close| {
+                // Unterminated strings don't score.
+                score * 5 + self.pair_by_close(close).map_or(0, |p| p.incomplete_score)
+            })),
+            _ => None,
+        }
+    }
 }
 
-fn is_open(c: char) -> bool {
-    matches!(c, '<' | '{' | '[' | '(')
+/// Checks a line one byte at a time. Apart from the stack of open pairs it only keeps the few
+/// bytes that might still turn out to be the start of a longer delimiter.
+struct Checker<'a> {
+    language: &'a Language,
+    /// Indices of open pairs and their columns.
+    stack: Vec<(usize, usize)>,
+    /// The quote of the string literal we are in, if any.
+    quote: Option<u8>,
+    /// Whether the previous byte was an escape in a string literal.
+    escaped: bool,
+    /// Bytes that haven't been looked at yet.
+    pending: Vec<u8>,
+    /// Column of the first pending byte.
+    column: usize,
+    /// Whether the line was already reported as corrupted, the rest of it is ignored.
+    failed: bool,
 }
 
-fn is_pair(open: char, close: char) -> bool {
-    matches!(
-        (open, close),
-        ('<', '>') | ('{', '}') | ('(', ')') | ('[', ']')
-    )
+impl<'a> Checker<'a> {
+    fn new(language: &'a Language) -> Self {
+        assert!(
+            language
+                .quotes
+                .iter()
+                .chain(&language.escape)
+                .all(char::is_ascii),
+            "quotes and escapes must be ASCII"
+        );
+        Self {
+            language,
+            stack: Vec::new(),
+            quote: None,
+            escaped: false,
+            pending: Vec::new(),
+            column: 0,
+            failed: false,
+        }
+    }
+
+    /// Feed the next byte of a line, without the line break. Returns the status as soon as the
+    /// line is known to be corrupted.
+    fn push(&mut self, b: u8) -> Option<Status> {
+        if self.failed {
+            return None;
+        }
+        self.pending.push(b);
+        self.process(false)
+    }
+
+    /// Finish the current line and get ready for the next one. Returns the status of the line,
+    /// unless it has been returned already.
+    fn end_line(&mut self) -> Option<Status> {
+        let status = if self.failed {
+            None
+        } else if let Some(status) = self.process(true) {
+            Some(status)
+        } else {
+            let mut missing: Vec<String> = self
+                .quote
+                .iter()
+                .map(|&q| (q as char).to_string())
+                .collect();
+            missing.extend(
+                self.stack
+                    .iter()
+                    .rev()
+                    .map(|&(open, _)| self.language.pairs[open].close.clone()),
+            );
+            if missing.is_empty() {
+                Some(Status::Valid)
+            } else {
+                Some(Status::Incomplete { missing })
+            }
+        };
+
+        *self = Self::new(self.language);
+        status
+    }
+
+    /// Consume pending bytes as long as it is clear what they are. At the end of the line
+    /// everything is clear.
+    fn process(&mut self, end: bool) -> Option<Status> {
+        while let Some(&first) = self.pending.first() {
+            let len = if let Some(q) = self.quote {
+                if self.escaped {
+                    self.escaped = false;
+                } else if Some(first as char) == self.language.escape {
+                    self.escaped = true;
+                } else if first == q {
+                    self.quote = None;
+                }
+                1
+            } else if self.in_strings() && self.language.quotes.contains(&(first as char)) {
+                self.quote = Some(first);
+                1
+            } else if !end && self.language.could_grow(&self.pending) {
+                // Wait for more bytes.
+                return None;
+            } else {
+                let top = self.stack.last().map(|&(open, _)| open);
+                match self.language.token(&self.pending, top) {
+                    Some((token, len)) => {
+                        if let Some(status) = self.apply(token) {
+                            self.failed = true;
+                            self.pending.clear();
+                            return Some(status);
+                        }
+                        len
+                    }
+                    // Anything else is not our business.
+                    None => 1,
+                }
+            };
+
+            // Only count the first byte of every UTF-8 char.
+            self.column += self
+                .pending
+                .drain(..len)
+                .filter(|b| b & 0xC0 != 0x80)
+                .count();
+        }
+        None
+    }
+
+    /// Whether quotes start string literals here.
+    fn in_strings(&self) -> bool {
+        self.stack
+            .last()
+            .is_some_and(|&(open, _)| self.language.pairs[open].strings)
+    }
+
+    /// Update the stack with a token at the current column.
+    fn apply(&mut self, token: Token) -> Option<Status> {
+        let column = self.column;
+        let closed = match token {
+            Token::Open(i) => {
+                self.stack.push((i, column));
+                return None;
+            }
+            Token::Close(i) => i,
+        };
+        let found = self.language.pairs[closed].close.clone();
+        match self.stack.pop() {
+            None => Some(Status::UnexpectedCloser { column, found }),
+            Some((open, opened_at)) if open != closed => Some(Status::Corrupted {
+                column,
+                expected: self.language.pairs[open].close.clone(),
+                found,
+                opened_at,
+            }),
+            Some(_) => None,
+        }
+    }
 }
 
-fn char_score_invalid(c: char) -> usize {
-    match c {
-        ')' => 3,
-        ']' => 57,
-        '}' => 1197,
-        '>' => 25137,
-        c => panic!("invalid char for score '{}'", c),
+/// Check every line of a stream and report the status of each line as soon as it is known.
+/// Lines are counted from zero.
+fn check_stream(
+    language: &Language,
+    reader: impl Read,
+    mut report: impl FnMut(usize, Status),
+) -> io::Result<()> {
+    let mut reader = BufReader::new(reader);
+    let mut checker = Checker::new(language);
+    let mut line = 0;
+    let mut in_line = false;
+
+    loop {
+        let buf = reader.fill_buf()?;
+        if buf.is_empty() {
+            break;
+        }
+        for &b in buf {
+            let status = if b == b'\n' {
+                in_line = false;
+                line += 1;
+                checker.end_line().map(|s| (line - 1, s))
+            } else {
+                in_line = true;
+                checker.push(b).map(|s| (line, s))
+            };
+            if let Some((line, status)) = status {
+                report(line, status);
+            }
+        }
+        let len = buf.len();
+        reader.consume(len);
+    }
+
+    if in_line {
+        if let Some(status) = checker.end_line() {
+            report(line, status);
+        }
     }
+    Ok(())
 }
 
-fn char_score_incomplete(c: char) -> usize {
-    match c {
-        '(' => 1,
-        '[' => 2,
-        '{' => 3,
-        '<' => 4,
-        c => panic!("invalid char for score '{}'", c),
+/// Check every line once and return the scores for both parts.
+fn solve(language: &Language, input: &str) -> (usize, usize) {
+    let mut corrupted = 0;
+    let mut incomplete = Vec::new();
+
+    for line in input.lines() {
+        let status = language.check(line);
+        corrupted += language.invalid_score(&status).unwrap_or(0);
+        incomplete.extend(language.incomplete_score(&status));
     }
+
+    incomplete.sort_unstable();
+    (corrupted, incomplete[incomplete.len() / 2])
 }
 
-fn input() -> Vec<Line> {
-    include_str!("../../input/10.txt")
-        .lines()
-        .map(|s| s.chars().collect())
-        .collect()
+#[cfg(test)]
+mod test {
+    use crate::{check_stream, solve, Language, Status};
+
+    #[test]
+    fn example() {
+        assert_eq!(
+            solve(&Language::aoc(), include_str!("../../input/10-test.txt")),
+            (26397, 288957)
+        );
+    }
+
+    #[test]
+    fn diagnostics() {
+        let aoc = Language::aoc();
+        assert_eq!(aoc.check("([]){}"), Status::Valid);
+        assert_eq!(
+            aoc.check("{([(<{}[<>[]}>{[]{[(<()>"),
+            Status::Corrupted {
+                column: 12,
+                expected: "]".to_string(),
+                found: "}".to_string(),
+                opened_at: 7
+            }
+        );
+        match aoc.check("[({(<(())[]>[[{[]{<()<>>") {
+            Status::Incomplete { missing } => assert_eq!(missing.concat(), "}}]])})]"),
+            status => panic!("unexpected {:?}", status),
+        }
+        assert_eq!(
+            aoc.check("()]"),
+            Status::UnexpectedCloser {
+                column: 2,
+                found: "]".to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn template() {
+        let template = Language::template();
+        assert_eq!(
+            template.check("{% if a %}{{ b(\"%}\") }}{% endif %}"),
+            Status::Valid
+        );
+        assert_eq!(
+            template.check("{% for x in y }}"),
+            Status::Corrupted {
+                column: 14,
+                expected: "%}".to_string(),
+                found: "}}".to_string(),
+                opened_at: 0
+            }
+        );
+        assert_eq!(template.check("Don't {{ x }}"), Status::Valid);
+        assert_eq!(
+            template.check("{% set d = {'a': {'b': 1}} %}"),
+            Status::Valid
+        );
+        assert_eq!(template.check("var x = {a:{b:1}};"), Status::Valid);
+        assert_eq!(template.check("{# don't #}"), Status::Valid);
+        assert_eq!(
+            template.check("{{ 'it\\'s' "),
+            Status::Incomplete {
+                missing: vec!["}}".to_string()]
+            }
+        );
+    }
+
+    #[test]
+    fn stream() {
+        let language = Language::template();
+        let input = "{{ a }}\n{% ü %}}\n{{ \"%}\"";
+        let mut statuses = Vec::new();
+        check_stream(&language, input.as_bytes(), |line, status| {
+            statuses.push((line, status))
+        })
+        .unwrap();
+        let expected: Vec<_> = input
+            .lines()
+            .map(|l| language.check(l))
+            .enumerate()
+            .collect();
+        assert_eq!(statuses, expected);
+        assert_eq!(
+            statuses[1].1,
+            Status::UnexpectedCloser {
+                column: 7,
+                found: "}".to_string()
+            }
+        );
+    }
 }
@@ -1,124 +1,469 @@
+use std::collections::{HashMap, VecDeque};
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::thread;
+use std::time::Duration;
+
 fn main() {
-    let (flashes, sync) = solve();
-    println!("First solution: {}", flashes);
-    println!("Second solution: {}", sync);
-}
-
-fn solve() -> (usize, usize) {
-    let mut field = input();
-    let mut flashes = 0;
-    let mut i = 0;
-
-    loop {
-        i += 1;
-
-        // Add one to each octopus
-        field.apply(|o| {
-            o.energy += 1;
-        });
-
-        // Do the flashing
-        let mut changed = true;
-        while changed {
-            changed = false;
-
-            for row in 0..10 {
-                for col in 0..10 {
-                    let current = &mut field.0[row][col];
-                    if current.energy > 9 && !current.did_flash {
-                        current.did_flash = true;
-                        changed = true;
-                        if i <= 100 {
-                            flashes += 1;
-                        }
-                        for (r, c) in neighbors(row, col) {
-                            field.0[r][c].energy += 1;
-                        }
-                    }
-                }
+    let mut args = env::args().skip(1);
+    let mut wrap = false;
+    let mut file = None;
+    let mut steps = None;
+    let mut animation = Animation::default();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--wrap" => wrap = true,
+            "--input" => file = Some(args.next().expect("missing input file")),
+            "--steps" => steps = Some(args.next().expect("missing steps").parse().unwrap()),
+            "--visualize" => animation.terminal = true,
+            "--delay" => animation.delay = Duration::from_millis(number(args.next())),
+            "--from" => animation.from = number(args.next()),
+            "--to" => animation.to = number(args.next()),
+            "--frames" => animation.frames = Some(args.next().expect("missing directory").into()),
+            err => panic!("unknown argument '{}'", err),
+        }
+    }
+
+    let text = match file {
+        Some(path) => fs::read_to_string(path).unwrap(),
+        None => include_str!("../../input/11.txt").to_string(),
+    };
+    let mut field = input(&text);
+    field.wrap = wrap;
+
+    if animation.terminal || animation.frames.is_some() {
+        animation.run(field);
+        return;
+    }
+
+    if let Some(steps) = steps {
+        let flashes = if steps <= RECORD_LIMIT {
+            Some(flashes(field, steps))
+        } else {
+            let history = History::record(field, RECORD_LIMIT);
+            if let Some((start, period)) = history.cycle {
+                println!("Repeats every {} steps after step {}", period, start);
             }
+            history.flashes_after(steps)
+        };
+        match flashes {
+            Some(flashes) => println!("Flashes after {} steps: {}", steps, flashes),
+            None => println!(
+                "The field doesn't repeat within {} steps, so {} steps are too many",
+                RECORD_LIMIT, steps
+            ),
         }
+        return;
+    }
+
+    println!("First solution: {}", flashes(field.clone(), 100));
+    let history = History::record(field, RECORD_LIMIT);
+    match (history.first_sync(), history.cycle) {
+        (Some(sync), _) => println!("Second solution: {}", sync),
+        (None, Some(_)) => println!("Second solution: the octopi never flash at the same time"),
+        (None, None) => println!(
+            "Second solution: the octopi don't flash at the same time within {} steps",
+            RECORD_LIMIT
+        ),
+    }
+}
 
-        // Have all octopi flashed?
-        if field.0.iter().flat_map(|r| r.iter()).all(|o| o.did_flash) {
-            return (flashes, i);
+/// Stop looking for a repeating field after this many steps, since every field on the way
+/// is kept.
+const RECORD_LIMIT: usize = 10_000;
+
+/// Total flashes in the first `steps` steps.
+fn flashes(field: Field, steps: usize) -> usize {
+    let simulation = Simulation { field, step: 0 };
+    simulation.take(steps).map(|r| r.flashed.len()).sum()
+}
+
+fn number<T: std::str::FromStr>(arg: Option<String>) -> T {
+    match arg.expect("missing number").parse() {
+        Ok(n) => n,
+        Err(_) => panic!("not a number"),
+    }
+}
+
+/// Settings for watching the octopi.
+#[derive(Debug)]
+struct Animation {
+    /// Draw the frames in the terminal.
+    terminal: bool,
+    delay: Duration,
+    /// First and last step to show. Step zero is the field before anything happened.
+    from: usize,
+    to: usize,
+    /// Directory to save every frame as a PPM image in.
+    frames: Option<PathBuf>,
+}
+
+impl Default for Animation {
+    fn default() -> Self {
+        Self {
+            terminal: false,
+            delay: Duration::from_millis(100),
+            from: 0,
+            to: 100,
+            frames: None,
         }
+    }
+}
 
-        // Reset octopi > 9
-        field.apply(|o| {
-            if o.energy > 9 {
-                o.energy = 0;
-                o.did_flash = false;
+impl Animation {
+    fn run(&self, field: Field) {
+        let mut simulation = Simulation { field, step: 0 };
+        let mut record = StepRecord {
+            step: 0,
+            flashed: Vec::new(),
+        };
+        loop {
+            if record.step >= self.from {
+                self.show(&simulation.field, &record);
             }
-        });
+            if record.step >= self.to {
+                break;
+            }
+            record = simulation.next().unwrap();
+        }
+    }
+
+    fn show(&self, field: &Field, record: &StepRecord) {
+        if self.terminal {
+            // Clear the screen and move to the top left corner.
+            print!("\x1b[2J\x1b[H");
+            print!("{}", field.ansi(&record.flashed));
+            println!("Step {}, {} flashes", record.step, record.flashed.len());
+            thread::sleep(self.delay);
+        }
+        if let Some(dir) = &self.frames {
+            let path = dir.join(format!("frame-{:05}.ppm", record.step));
+            fs::write(path, field.ppm(&record.flashed, 8)).unwrap();
+        }
     }
 }
 
-fn neighbors(row: usize, col: usize) -> Vec<(usize, usize)> {
-    let mut neighbors = Vec::new();
+/// What happened in a single step.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct StepRecord {
+    /// Counted from one.
+    step: usize,
+    /// Positions of the octopi that flashed, in the order they flashed.
+    flashed: Vec<(usize, usize)>,
+}
+
+/// Steps through the simulation forever.
+struct Simulation {
+    field: Field,
+    step: usize,
+}
 
-    let mut rs = vec![row];
-    let mut cs = vec![col];
+impl Iterator for Simulation {
+    type Item = StepRecord;
 
-    if row > 0 {
-        rs.push(row - 1);
+    fn next(&mut self) -> Option<StepRecord> {
+        self.step += 1;
+        Some(StepRecord {
+            step: self.step,
+            flashed: self.field.step(),
+        })
     }
-    if row < 9 {
-        rs.push(row + 1);
+}
+
+/// All steps up to the point where the field starts repeating itself, or up to a limit.
+#[derive(Debug)]
+struct History {
+    /// Number of octopi.
+    size: usize,
+    /// Flashes in each step. The first entry is for step one.
+    flashes: Vec<usize>,
+    /// The step after which the fields start repeating and the number of steps until they do.
+    /// `None` if that didn't happen before the limit.
+    cycle: Option<(usize, usize)>,
+}
+
+impl History {
+    /// Simulate until a field repeats or `limit` steps are done. The number of possible fields
+    /// is finite, so they always repeat eventually, but that can take a long time.
+    fn record(field: Field, limit: usize) -> Self {
+        let size = field.rows() * field.cols();
+        let mut seen = HashMap::new();
+        seen.insert(field.energy.clone(), 0);
+
+        let mut simulation = Simulation { field, step: 0 };
+        let mut flashes = Vec::new();
+        for _ in 0..limit {
+            let record = simulation.next().unwrap();
+            flashes.push(record.flashed.len());
+            if let Some(start) = seen.insert(simulation.field.energy.clone(), record.step) {
+                return Self {
+                    size,
+                    flashes,
+                    cycle: Some((start, record.step - start)),
+                };
+            }
+        }
+        Self {
+            size,
+            flashes,
+            cycle: None,
+        }
+    }
+
+    /// Total flashes in the first `steps` steps. `None` if that is past the recorded steps and
+    /// no cycle was found.
+    fn flashes_after(&self, steps: usize) -> Option<usize> {
+        let sum = |n: usize| self.flashes[..n].iter().sum::<usize>();
+        if steps <= self.flashes.len() {
+            return Some(sum(steps));
+        }
+
+        let (start, period) = self.cycle?;
+        let per_cycle = sum(start + period) - sum(start);
+        let cycles = (steps - start) / period;
+        let rest = (steps - start) % period;
+        Some(sum(start + rest) + cycles * per_cycle)
+    }
+
+    /// The first step where all octopi flash. If that doesn't happen before the field repeats,
+    /// it never will.
+    fn first_sync(&self) -> Option<usize> {
+        self.flashes
+            .iter()
+            .position(|&f| f == self.size)
+            .map(|i| i + 1)
     }
-    if col > 0 {
-        cs.push(col - 1);
+}
+
+#[derive(Clone)]
+struct Field {
+    energy: Vec<Vec<u32>>,
+    /// Whether octopi on the edges are neighbors of the ones on the opposite edge.
+    wrap: bool,
+}
+
+impl Field {
+    fn rows(&self) -> usize {
+        self.energy.len()
     }
-    if col < 9 {
-        cs.push(col + 1);
+
+    fn cols(&self) -> usize {
+        self.energy[0].len()
     }
 
-    for r in rs {
-        for &c in &cs {
-            if r == row && c == col {
-                continue;
+    /// Advance by one step and return the octopi that flashed.
+    fn step(&mut self) -> Vec<(usize, usize)> {
+        // Add one to each octopus and remember the ones that are about to flash.
+        let mut queue = VecDeque::new();
+        for (row, line) in self.energy.iter_mut().enumerate() {
+            for (col, energy) in line.iter_mut().enumerate() {
+                *energy += 1;
+                if *energy == 10 {
+                    queue.push_back((row, col));
+                }
+            }
+        }
+
+        // Every octopus reaches 10 only once, so each one flashes at most once.
+        let mut flashed = Vec::new();
+        while let Some((row, col)) = queue.pop_front() {
+            flashed.push((row, col));
+            for (r, c) in self.neighbors(row, col) {
+                self.energy[r][c] += 1;
+                if self.energy[r][c] == 10 {
+                    queue.push_back((r, c));
+                }
             }
-            neighbors.push((r, c));
         }
+
+        // Reset octopi > 9
+        for energy in self.energy.iter_mut().flatten() {
+            if *energy > 9 {
+                *energy = 0;
+            }
+        }
+
+        flashed
     }
 
-    neighbors
-}
+    fn neighbors(&self, row: usize, col: usize) -> Vec<(usize, usize)> {
+        let (rows, cols) = (self.rows() as isize, self.cols() as isize);
+        let mut neighbors = Vec::new();
 
-struct Field(Vec<Vec<Octopus>>);
+        for dr in -1..=1 {
+            for dc in -1..=1 {
+                let (mut r, mut c) = (row as isize + dr, col as isize + dc);
+                if self.wrap {
+                    r = r.rem_euclid(rows);
+                    c = c.rem_euclid(cols);
+                } else if r < 0 || r >= rows || c < 0 || c >= cols {
+                    continue;
+                }
+                neighbors.push((r as usize, c as usize));
+            }
+        }
+
+        // On tiny grids with wrapping, the same octopus can be reached in multiple ways.
+        neighbors.sort_unstable();
+        neighbors.dedup();
+        neighbors.retain(|&n| n != (row, col));
+        neighbors
+    }
+}
 
 impl Field {
-    /// Do something with every octopus
-    fn apply<F>(&mut self, mut op: F)
-    where
-        F: FnMut(&mut Octopus),
-    {
-        for row in 0..10 {
-            for col in 0..10 {
-                op(&mut self.0[row][col])
+    /// Draw the energy levels with a brightness that grows with the energy. Octopi that just
+    /// flashed are highlighted.
+    fn ansi(&self, flashed: &[(usize, usize)]) -> String {
+        let mut result = String::new();
+        for (row, line) in self.energy.iter().enumerate() {
+            for (col, &energy) in line.iter().enumerate() {
+                let (r, g, b) = color(energy, flashed.contains(&(row, col)));
+                result += &format!("\x1b[38;2;{};{};{}m{}", r, g, b, energy);
             }
+            result += "\x1b[0m\n";
         }
+        result
+    }
+
+    /// A single animation frame, blown up by `scale`, with the same colors as in the terminal.
+    fn ppm(&self, flashed: &[(usize, usize)], scale: usize) -> Vec<u8> {
+        let (width, height) = (self.cols() * scale, self.rows() * scale);
+        let mut result = format!("P6\n{} {}\n255\n", width, height).into_bytes();
+        for y in 0..height {
+            for x in 0..width {
+                let (row, col) = (y / scale, x / scale);
+                let (r, g, b) = color(self.energy[row][col], flashed.contains(&(row, col)));
+                result.extend([r, g, b]);
+            }
+        }
+        result
+    }
+}
+
+fn color(energy: u32, flashed: bool) -> (u8, u8, u8) {
+    if flashed {
+        (255, 230, 90)
+    } else {
+        let level = 30 + energy as u8 * 15;
+        (level / 2, level / 2, level)
     }
 }
 
-struct Octopus {
-    energy: u32,
-    did_flash: bool,
+fn input(s: &str) -> Field {
+    let energy: Vec<Vec<u32>> = s
+        .lines()
+        .map(|l| l.chars().map(|c| c.to_digit(10).unwrap()).collect())
+        .collect();
+    assert!(
+        energy.iter().all(|l| l.len() == energy[0].len()),
+        "field must be rectangular"
+    );
+    Field {
+        energy,
+        wrap: false,
+    }
 }
 
-fn input() -> Field {
-    Field(
-        include_str!("../../input/11.txt")
-            .lines()
-            .map(|l| {
-                l.chars()
-                    .map(|c| c.to_digit(10).unwrap())
-                    .map(|n| Octopus {
-                        energy: n,
-                        did_flash: false,
-                    })
-                    .collect()
-            })
-            .collect(),
-    )
+#[cfg(test)]
+mod test {
+    use crate::{flashes, input, Animation, History};
+    use std::{fs, time::Duration};
+
+    #[test]
+    fn example() {
+        let field = input(include_str!("../../input/11-test.txt"));
+        assert_eq!(flashes(field.clone(), 10), 204);
+        assert_eq!(flashes(field.clone(), 100), 1656);
+
+        let history = History::record(field, 1000);
+        assert_eq!(history.flashes_after(100), Some(1656));
+        assert_eq!(history.first_sync(), Some(195));
+        assert_eq!(history.cycle, Some((195, 10)));
+    }
+
+    #[test]
+    fn skip_cycles() {
+        let field = input(include_str!("../../input/11-test.txt"));
+        let history = History::record(field.clone(), 1000);
+        assert_eq!(history.flashes_after(1234), Some(flashes(field, 1234)));
+    }
+
+    #[test]
+    fn limit() {
+        let field = input(include_str!("../../input/11-test.txt"));
+        let history = History::record(field, 50);
+        assert_eq!(history.cycle, None);
+        assert_eq!(history.flashes_after(10), Some(204));
+        assert_eq!(history.flashes_after(51), None);
+        assert_eq!(history.first_sync(), None);
+    }
+
+    #[test]
+    fn neighbors() {
+        let mut field = input("1234\n5678\n9012");
+        assert_eq!(field.neighbors(0, 0), vec![(0, 1), (1, 0), (1, 1)]);
+        assert_eq!(field.neighbors(2, 3).len(), 3);
+        field.wrap = true;
+        assert_eq!(
+            field.neighbors(0, 0),
+            vec![
+                (0, 1),
+                (0, 3),
+                (1, 0),
+                (1, 1),
+                (1, 3),
+                (2, 0),
+                (2, 1),
+                (2, 3)
+            ]
+        );
+    }
+
+    #[test]
+    fn ppm() {
+        let mut field = input("19\n11");
+        let flashed = field.step();
+        assert_eq!(flashed, vec![(0, 1)]);
+
+        let ppm = field.ppm(&flashed, 3);
+        let header = b"P6\n6 6\n255\n";
+        assert!(ppm.starts_with(header));
+        assert_eq!(ppm.len(), header.len() + 6 * 6 * 3);
+        let pixel = |x: usize, y: usize| {
+            let i = header.len() + (y * 6 + x) * 3;
+            [ppm[i], ppm[i + 1], ppm[i + 2]]
+        };
+        assert_eq!(pixel(3, 0), [255, 230, 90]);
+        assert_eq!(pixel(5, 2), [255, 230, 90]);
+        // Everything else went up to 3.
+        assert_eq!(pixel(0, 0), [37, 37, 75]);
+        assert_eq!(pixel(5, 5), [37, 37, 75]);
+    }
+
+    #[test]
+    fn frames() {
+        let dir = std::env::temp_dir().join(format!("aoc-11-frames-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let animation = Animation {
+            terminal: false,
+            delay: Duration::ZERO,
+            from: 2,
+            to: 4,
+            frames: Some(dir.clone()),
+        };
+        animation.run(input(include_str!("../../input/11-test.txt")));
+
+        let mut names: Vec<String> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        names.sort();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(
+            names,
+            ["frame-00002.ppm", "frame-00003.ppm", "frame-00004.ppm"]
+        );
+    }
 }
@@ -1,101 +1,530 @@
 use std::collections::HashMap;
+use std::env;
+use std::fmt::Write;
+use std::hash::Hash;
 
 fn main() {
-    let puzzle = Network::from_str(include_str!("../../input/12.txt"));
-    println!("First solution: {}", puzzle.solve(Path::default(), false));
-    println!("Second solution: {}", puzzle.solve(Path::default(), true));
+    let mut args = env::args().skip(1);
+    let mut output = Output::Count;
+    let mut dot = false;
+    let mut policy = None;
+    let mut max_len = None;
+    let (mut through, mut waypoints) = (Vec::new(), Vec::new());
+    let (mut start, mut end) = ("start".to_string(), "end".to_string());
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--paths" => output = Output::List,
+            "--dot" => dot = true,
+            "--usage" => output = Output::Usage,
+            "--policy" => policy = Some(args.next().expect("missing policy")),
+            "--start" => start = args.next().expect("missing cave"),
+            "--end" => end = args.next().expect("missing cave"),
+            "--through" => through.push(args.next().expect("missing cave")),
+            "--waypoints" => {
+                waypoints = args
+                    .next()
+                    .expect("missing caves")
+                    .split(',')
+                    .map(String::from)
+                    .collect()
+            }
+            "--max-len" => max_len = Some(args.next().unwrap().parse().unwrap()),
+            err => panic!("unknown argument '{}'", err),
+        }
+    }
+
+    let puzzle = Network::from_str(include_str!("../../input/12.txt")).with_ends(&start, &end);
+    let filter = PathFilter {
+        through: through.iter().map(|c| puzzle.cave(c)).collect(),
+        waypoints: waypoints.iter().map(|c| puzzle.cave(c)).collect(),
+        max_len,
+    };
+    if dot {
+        print!("{}", puzzle.dot(None));
+        return;
+    }
+    if output == Output::Count && policy.is_none() && filter.is_empty() {
+        println!("First solution: {}", puzzle.solve(&Revisits(0)));
+        println!("Second solution: {}", puzzle.solve(&Revisits(1)));
+        return;
+    }
+
+    let policy = policy.unwrap_or_else(|| "revisits:0".to_string());
+    let (kind, arg) = policy
+        .split_once(':')
+        .expect("policies look like kind:argument");
+    match kind {
+        "revisits" => report(&puzzle, &Revisits(arg.parse().unwrap()), output, filter),
+        "each" => report(&puzzle, &EachSmall(limit(arg)), output, filter),
+        "limits" => {
+            let limits = arg
+                .split(',')
+                .map(|l| l.split_once('=').expect("limits look like cave=n"))
+                .map(|(cave, n)| (cave, limit(n)));
+            report(&puzzle, &PerCave::new(&puzzle, limits), output, filter)
+        }
+        err => panic!("unknown policy '{}'", err),
+    }
+}
+
+/// Visits are counted in a `u8`, so limits can't go higher than that.
+fn limit(arg: &str) -> u8 {
+    arg.parse()
+        .unwrap_or_else(|_| panic!("visit limits go from 0 to {}, not '{}'", u8::MAX, arg))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Output {
+    /// Number of paths.
+    Count,
+    /// Every path.
+    List,
+    /// Graphviz DOT with the number of paths that use every connection.
+    Usage,
+}
+
+fn report<P: VisitPolicy>(network: &Network, policy: &P, output: Output, filter: PathFilter) {
+    match output {
+        // Counting is only memoized for all paths, the filtered ones have to be walked.
+        Output::Count if filter.is_empty() => println!("Paths: {}", network.solve(policy)),
+        Output::Count => println!("Paths: {}", network.paths(policy, filter).count()),
+        Output::List => {
+            for path in network.sorted_paths(policy, filter) {
+                println!("{}", path.join(","));
+            }
+        }
+        Output::Usage => print!("{}", network.dot(Some(&network.usage(policy, filter)))),
+    }
 }
 
 type Cave = &'static str;
 
-struct Network {
-    connections: HashMap<Cave, Vec<Cave>>,
+/// Decides how often caves may be visited. The state has to capture everything that decisions
+/// depend on, because path counts are memoized on it.
+trait VisitPolicy {
+    type State: Clone + Eq + Hash;
+
+    /// State right after entering the start cave.
+    fn initial(&self, network: &Network) -> Self::State;
+
+    /// State after visiting a cave, or `None` if that is not allowed.
+    fn visit(&self, network: &Network, state: &Self::State, cave: usize) -> Option<Self::State>;
 }
 
-#[derive(Clone, Debug, Default)]
-struct Path {
-    caves: Vec<Cave>,
+/// Small caves may be visited once, except for a single one that may be visited another `k`
+/// times. The state is the set of visited small caves and the revisited one with its revisits.
+struct Revisits(usize);
+
+impl VisitPolicy for Revisits {
+    type State = (u64, Option<(usize, usize)>);
+
+    fn initial(&self, network: &Network) -> Self::State {
+        (network.small[network.start()].unwrap_or(0), None)
+    }
+
+    fn visit(
+        &self,
+        network: &Network,
+        &(visited, revisited): &Self::State,
+        cave: usize,
+    ) -> Option<Self::State> {
+        match (network.small[cave], revisited) {
+            // Big caves can be visited any number of times
+            (None, _) => Some((visited, revisited)),
+            (Some(bit), _) if visited & bit == 0 => Some((visited | bit, revisited)),
+            (Some(_), None) if self.0 > 0 => Some((visited, Some((cave, 1)))),
+            (Some(_), Some((c, n))) if c == cave && n < self.0 => Some((visited, Some((c, n + 1)))),
+            _ => None,
+        }
+    }
 }
 
-impl Network {
-    // Recursively count the number of paths.
-    fn solve(&self, current: Path, part2: bool) -> usize {
-        if current.end() == "end" {
-            return 1;
+/// Every small cave may be visited up to this many times.
+struct EachSmall(u8);
+
+impl VisitPolicy for EachSmall {
+    /// Number of visits for every cave.
+    type State = Vec<u8>;
+
+    /// The start can't be visited again, so its visit doesn't need to be counted.
+    fn initial(&self, network: &Network) -> Self::State {
+        vec![0; network.names.len()]
+    }
+
+    fn visit(&self, network: &Network, state: &Self::State, cave: usize) -> Option<Self::State> {
+        let limit = network.small[cave].map(|_| self.0);
+        count_visit(limit, state, cave)
+    }
+}
+
+/// Explicit visit limits for some caves, by cave index. The others are visited like in the
+/// first part: small caves once and big caves any number of times.
+struct PerCave(Vec<Option<u8>>);
+
+impl PerCave {
+    fn new<'a>(network: &Network, limits: impl IntoIterator<Item = (&'a str, u8)>) -> Self {
+        let mut per_cave = vec![None; network.names.len()];
+        for (cave, limit) in limits {
+            per_cave[network.cave(cave)] = Some(limit);
         }
+        Self(per_cave)
+    }
+}
 
-        let mut n = 0;
+impl VisitPolicy for PerCave {
+    /// Number of visits for every cave.
+    type State = Vec<u8>;
 
-        for &next in &self.connections[current.end()] {
-            let can_visit = if part2 {
-                current.can_visit_plus(next)
-            } else {
-                current.can_visit(next)
-            };
+    /// The start can't be visited again, so its visit doesn't need to be counted.
+    fn initial(&self, network: &Network) -> Self::State {
+        vec![0; network.names.len()]
+    }
 
-            if can_visit && next != "start" {
-                let mut next_path = current.clone();
-                next_path.visit(next);
-                n += self.solve(next_path, part2);
-            }
+    fn visit(&self, network: &Network, state: &Self::State, cave: usize) -> Option<Self::State> {
+        let limit = self.0[cave].or(network.small[cave].map(|_| 1));
+        count_visit(limit, state, cave)
+    }
+}
+
+/// Count a visit to a cave, unless that exceeds its limit. Limits fit into the counter, so it
+/// can't overflow.
+fn count_visit(limit: Option<u8>, state: &[u8], cave: usize) -> Option<Vec<u8>> {
+    match limit {
+        Some(limit) if state[cave] >= limit => None,
+        // There is no point in counting visits to caves without a limit.
+        None => Some(state.to_vec()),
+        Some(_) => {
+            let mut state = state.to_vec();
+            state[cave] += 1;
+            Some(state)
         }
-        n
     }
+}
 
-    fn from_str(s: &'static str) -> Self {
-        let mut connections: HashMap<Cave, Vec<Cave>> = HashMap::new();
-        for (from, to) in s.lines().filter_map(|l| l.trim().split_once('-')) {
-            connections.entry(from).or_default().push(to);
-            connections.entry(to).or_default().push(from);
+/// Conditions that listed paths have to meet. Caves are given by their index.
+#[derive(Debug, Default)]
+struct PathFilter {
+    /// Caves that have to be visited, in any order.
+    through: Vec<usize>,
+    /// Caves that have to be visited in this order.
+    waypoints: Vec<usize>,
+    /// Maximum number of caves in a path, including start and end.
+    max_len: Option<usize>,
+}
+
+impl PathFilter {
+    fn is_empty(&self) -> bool {
+        self.through.is_empty() && self.waypoints.is_empty() && self.max_len.is_none()
+    }
+
+    fn accepts(&self, path: &[usize]) -> bool {
+        let mut rest = path.iter();
+        self.through.iter().all(|c| path.contains(c))
+            && self.waypoints.iter().all(|w| rest.any(|c| c == w))
+    }
+}
+
+/// Lazily walks all paths from start to end, depth first.
+struct Paths<'a, P: VisitPolicy> {
+    network: &'a Network,
+    policy: &'a P,
+    filter: PathFilter,
+    /// The current path.
+    stack: Vec<Frame<P::State>>,
+}
+
+struct Frame<S> {
+    cave: usize,
+    /// Index of the next connection to try.
+    next: usize,
+    state: S,
+}
+
+impl<P: VisitPolicy> Iterator for Paths<'_, P> {
+    type Item = Vec<Cave>;
+
+    fn next(&mut self) -> Option<Vec<Cave>> {
+        let network = self.network;
+        let max_len = self.filter.max_len.unwrap_or(usize::MAX);
+
+        while !self.stack.is_empty() {
+            let depth = self.stack.len();
+            let frame = self.stack.last_mut().unwrap();
+            if frame.cave == network.end() {
+                let path: Vec<usize> = self.stack.iter().map(|f| f.cave).collect();
+                self.stack.pop();
+                if self.filter.accepts(&path) {
+                    return Some(path.into_iter().map(|c| network.names[c]).collect());
+                }
+                continue;
+            }
+
+            let connections = &network.connections[frame.cave];
+            if frame.next == connections.len() || depth == max_len {
+                self.stack.pop();
+                continue;
+            }
+            let next = connections[frame.next];
+            frame.next += 1;
+            if next == network.start() {
+                continue;
+            }
+
+            if let Some(state) = self.policy.visit(network, &frame.state, next) {
+                self.stack.push(Frame {
+                    cave: next,
+                    next: 0,
+                    state,
+                });
+            }
         }
 
-        Self { connections }
+        None
     }
 }
 
-impl Path {
-    fn can_visit(&self, c: Cave) -> bool {
-        if c.to_lowercase() == c {
-            // Small caves may be visitied at most once
-            !self.caves.iter().any(|&cc| c == cc)
-        } else {
-            // Big caves can be visited any number of times
-            true
+/// Caves are interned, so they are referred to by their index in `names`.
+struct Network {
+    names: Vec<Cave>,
+    connections: Vec<Vec<usize>>,
+    /// Bit that marks a small cave as visited, `None` for big caves.
+    small: Vec<Option<u64>>,
+    /// `None` if the input has no cave called like that and no other one was picked.
+    start: Option<usize>,
+    end: Option<usize>,
+}
+
+impl Network {
+    /// Count the number of paths from start to end.
+    fn solve<P: VisitPolicy>(&self, policy: &P) -> usize {
+        let mut memo = HashMap::new();
+        self.count(policy, self.start(), policy.initial(self), &mut memo)
+    }
+
+    /// All paths from start to end that pass the filter, in no particular order.
+    fn paths<'a, P: VisitPolicy>(&'a self, policy: &'a P, filter: PathFilter) -> Paths<'a, P> {
+        Paths {
+            network: self,
+            policy,
+            filter,
+            stack: vec![Frame {
+                cave: self.start(),
+                next: 0,
+                state: policy.initial(self),
+            }],
         }
     }
-    // Allows a single small cave to be visited twice.
-    fn can_visit_plus(&self, c: Cave) -> bool {
-        if c.to_lowercase() == c {
-            let already_visited = self.caves.iter().any(|&cc| c == cc);
-            if already_visited {
-                // We have already been to this small cave. Find out if we visited any single
-                // cave twice already.
-                let mut only_small_caves: Vec<Cave> = self
-                    .caves
-                    .clone()
-                    .into_iter()
-                    .filter(|&s| s.to_lowercase() == s)
-                    .collect();
-                only_small_caves.sort_unstable();
-                let a = only_small_caves.len();
-                only_small_caves.dedup();
-                let b = only_small_caves.len();
-                return a == b;
+
+    /// Like `paths`, but sorted so that the output is stable.
+    fn sorted_paths<P: VisitPolicy>(&self, policy: &P, filter: PathFilter) -> Vec<Vec<Cave>> {
+        let mut paths: Vec<Vec<Cave>> = self.paths(policy, filter).collect();
+        paths.sort_unstable();
+        paths
+    }
+
+    /// Number of paths from a cave to the end, memoized by cave and visit state.
+    fn count<P: VisitPolicy>(
+        &self,
+        policy: &P,
+        cave: usize,
+        state: P::State,
+        memo: &mut HashMap<(usize, P::State), usize>,
+    ) -> usize {
+        if cave == self.end() {
+            return 1;
+        }
+        if let Some(&n) = memo.get(&(cave, state.clone())) {
+            return n;
+        }
+
+        let mut n = 0;
+        for &next in &self.connections[cave] {
+            if next == self.start() {
+                continue;
+            }
+            if let Some(next_state) = policy.visit(self, &state, next) {
+                n += self.count(policy, next, next_state, memo);
             }
         }
-        true
+
+        memo.insert((cave, state), n);
+        n
     }
-    fn visit(&mut self, c: Cave) {
-        self.caves.push(c);
+
+    /// How often every connection is used by all paths that pass the filter, by the caves it
+    /// connects in ascending order. This walks every single path, so it only works for networks
+    /// that don't have too many of them.
+    fn usage<P: VisitPolicy>(
+        &self,
+        policy: &P,
+        filter: PathFilter,
+    ) -> HashMap<(Cave, Cave), usize> {
+        let mut usage = HashMap::new();
+        for path in self.paths(policy, filter) {
+            for pair in path.windows(2) {
+                let key = (pair[0].min(pair[1]), pair[0].max(pair[1]));
+                *usage.entry(key).or_default() += 1;
+            }
+        }
+        usage
     }
-    fn end(&self) -> Cave {
-        self.caves.last().unwrap_or(&"start")
+
+    /// Graphviz DOT with big caves as boxes, small caves as ellipses and start and end filled.
+    /// Connections are labeled and thickened by their usage, if given.
+    fn dot(&self, usage: Option<&HashMap<(Cave, Cave), usize>>) -> String {
+        let mut dot = String::from("graph caves {\n");
+        for (id, &name) in self.names.iter().enumerate() {
+            let shape = if self.small[id].is_some() {
+                "ellipse"
+            } else {
+                "box"
+            };
+            let fill = if Some(id) == self.start {
+                ", style=filled, fillcolor=palegreen"
+            } else if Some(id) == self.end {
+                ", style=filled, fillcolor=lightcoral"
+            } else {
+                ""
+            };
+            writeln!(dot, "    \"{}\" [shape={}{}];", name, shape, fill).unwrap();
+        }
+
+        let max = usage.and_then(|u| u.values().max().copied()).unwrap_or(0);
+        for (from, connections) in self.connections.iter().enumerate() {
+            for &to in connections.iter().filter(|&&to| from < to) {
+                let (a, b) = (self.names[from], self.names[to]);
+                write!(dot, "    \"{}\" -- \"{}\"", a, b).unwrap();
+                if let Some(usage) = usage {
+                    let n = usage.get(&(a.min(b), a.max(b))).copied().unwrap_or(0);
+                    let width = 1.0 + 4.0 * n as f64 / max.max(1) as f64;
+                    write!(dot, " [label=\"{}\", penwidth={:.1}]", n, width).unwrap();
+                }
+                dot += ";\n";
+            }
+        }
+
+        dot += "}\n";
+        dot
+    }
+
+    /// Index of the cave with this name. Panics if there is none.
+    fn cave(&self, name: &str) -> usize {
+        self.names
+            .iter()
+            .position(|&n| n == name)
+            .unwrap_or_else(|| panic!("unknown cave '{}'", name))
+    }
+
+    fn start(&self) -> usize {
+        self.start.expect("the network has no start")
+    }
+
+    fn end(&self) -> usize {
+        self.end.expect("the network has no end")
+    }
+
+    /// Let paths go between other caves than `start` and `end`.
+    fn with_ends(mut self, start: &str, end: &str) -> Self {
+        self.start = Some(self.cave(start));
+        self.end = Some(self.cave(end));
+        self
+    }
+
+    fn from_str(s: &'static str) -> Self {
+        let mut network = Self {
+            names: Vec::new(),
+            connections: Vec::new(),
+            small: Vec::new(),
+            start: None,
+            end: None,
+        };
+        let mut ids = HashMap::new();
+        let mut small_caves = 0;
+        let mut intern = |network: &mut Self, name: Cave| {
+            *ids.entry(name).or_insert_with(|| {
+                let small = if name.to_lowercase() == name {
+                    assert!(small_caves < u64::BITS, "too many small caves");
+                    small_caves += 1;
+                    Some(1 << (small_caves - 1))
+                } else {
+                    None
+                };
+                network.names.push(name);
+                network.connections.push(Vec::new());
+                network.small.push(small);
+                network.names.len() - 1
+            })
+        };
+
+        for (from, to) in s.lines().filter_map(|l| l.trim().split_once('-')) {
+            let from = intern(&mut network, from);
+            let to = intern(&mut network, to);
+            network.connections[from].push(to);
+            network.connections[to].push(from);
+        }
+        // Only look for the usual ends, so that networks without them don't get extra caves.
+        network.start = ids.get("start").copied();
+        network.end = ids.get("end").copied();
+
+        network
     }
 }
 
 #[cfg(test)]
 mod test {
-    use crate::{Network, Path};
+    use crate::{count_visit, EachSmall, Network, PathFilter, PerCave, Revisits};
+
+    const SMALL: &str = r"start-A
+        start-b
+        A-c
+        A-b
+        b-d
+        A-end
+        b-end
+        ";
+
+    #[test]
+    fn paths() {
+        let network = Network::from_str(SMALL);
+        let paths: Vec<String> = network
+            .sorted_paths(&Revisits(0), PathFilter::default())
+            .iter()
+            .map(|p| p.join(","))
+            .collect();
+        assert_eq!(
+            paths,
+            [
+                "start,A,b,A,c,A,end",
+                "start,A,b,A,end",
+                "start,A,b,end",
+                "start,A,c,A,b,A,end",
+                "start,A,c,A,b,end",
+                "start,A,c,A,end",
+                "start,A,end",
+                "start,b,A,c,A,end",
+                "start,b,A,end",
+                "start,b,end",
+            ]
+        );
+        assert_eq!(
+            network.paths(&Revisits(1), PathFilter::default()).count(),
+            network.solve(&Revisits(1))
+        );
+    }
+
+    #[test]
+    fn filters() {
+        let network = Network::from_str(SMALL);
+        let filter = PathFilter {
+            through: vec![network.cave("c")],
+            waypoints: vec![network.cave("b"), network.cave("c")],
+            max_len: Some(6),
+        };
+        assert_eq!(
+            network.sorted_paths(&Revisits(0), filter),
+            [vec!["start", "b", "A", "c", "A", "end"]]
+        );
+    }
 
     #[test]
     fn input10() {
@@ -110,7 +539,7 @@ mod test {
         b-end
         "
             )
-            .solve(Path::default(), false),
+            .solve(&Revisits(0)),
             10
         );
     }
@@ -128,7 +557,7 @@ mod test {
         b-end
         "
             )
-            .solve(Path::default(), true),
+            .solve(&Revisits(1)),
             36
         );
     }
@@ -149,7 +578,7 @@ mod test {
         kj-dc
         "
             )
-            .solve(Path::default(), false),
+            .solve(&Revisits(0)),
             19
         );
     }
@@ -178,8 +607,67 @@ mod test {
         start-RW
         "
             )
-            .solve(Path::default(), false),
+            .solve(&Revisits(0)),
             226
         );
     }
+
+    #[test]
+    fn policies() {
+        let network = Network::from_str(SMALL);
+        assert_eq!(network.solve(&EachSmall(1)), 10);
+        assert_eq!(
+            count_visit(Some(u8::MAX), &[u8::MAX - 1], 0),
+            Some(vec![u8::MAX])
+        );
+        assert_eq!(count_visit(Some(u8::MAX), &[u8::MAX], 0), None);
+        assert!(network.solve(&Revisits(2)) > network.solve(&Revisits(1)));
+        assert!(network.solve(&EachSmall(2)) > network.solve(&Revisits(1)));
+
+        // Only b may be visited twice, so take the second part's paths where that holds.
+        let expected = network
+            .paths(&Revisits(1), PathFilter::default())
+            .filter(|p| {
+                ["c", "d"]
+                    .iter()
+                    .all(|c| p.iter().filter(|&x| x == c).count() < 2)
+            })
+            .count();
+        let only_b = PerCave::new(&network, [("b", 2)]);
+        assert_eq!(network.solve(&only_b), expected);
+        assert_eq!(
+            network.paths(&only_b, PathFilter::default()).count(),
+            expected
+        );
+    }
+
+    #[test]
+    fn ends() {
+        let network = Network::from_str("x-y\ny-z").with_ends("z", "x");
+        assert_eq!(network.names, ["x", "y", "z"]);
+        assert_eq!(network.solve(&Revisits(0)), 1);
+        assert_eq!(
+            network.sorted_paths(&Revisits(0), PathFilter::default()),
+            [vec!["z", "y", "x"]]
+        );
+    }
+
+    #[test]
+    fn dot() {
+        let network = Network::from_str("start-A\nA-b\nA-end");
+        let usage = network.usage(&Revisits(0), PathFilter::default());
+        assert_eq!(
+            network.dot(Some(&usage)),
+            r#"graph caves {
+    "start" [shape=ellipse, style=filled, fillcolor=palegreen];
+    "A" [shape=box];
+    "b" [shape=ellipse];
+    "end" [shape=ellipse, style=filled, fillcolor=lightcoral];
+    "start" -- "A" [label="2", penwidth=5.0];
+    "A" -- "b" [label="2", penwidth=5.0];
+    "A" -- "end" [label="2", penwidth=5.0];
+}
+"#
+        );
+    }
 }
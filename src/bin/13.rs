@@ -1,25 +1,131 @@
-use std::{collections::HashSet, fmt::Display};
+use std::{collections::HashSet, env, fmt::Display, fmt::Write, fs};
 
 fn main() {
-    let (mut paper, mut instructions) = input();
+    let mut args = env::args().skip(1);
+    let mut file = None;
+    let mut counts = false;
+    let mut stage = None;
+    let (mut svg, mut pbm, mut animation) = (None, None, None);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--input" => file = Some(args.next().expect("missing input file")),
+            "--counts" => counts = true,
+            "--stage" => stage = Some(args.next().expect("missing stage").parse().unwrap()),
+            "--svg" => svg = Some(args.next().expect("missing output file")),
+            "--pbm" => pbm = Some(args.next().expect("missing output file")),
+            "--animate" => animation = Some(args.next().expect("missing output file")),
+            err => panic!("unknown argument '{}'", err),
+        }
+    }
 
-    paper.apply(instructions.next().unwrap());
-    println!("First solution: {}", paper.0.len());
+    let text = match file {
+        Some(path) => fs::read_to_string(path).unwrap(),
+        None => include_str!("../../input/13.txt").to_string(),
+    };
+    let (paper, instructions) = input(&text);
+    let instructions: Vec<Instruction> = instructions.collect();
 
-    for i in instructions {
-        paper.apply(i);
+    // The paper before the first fold and after every fold.
+    let mut stages = vec![paper];
+    for (i, &instruction) in instructions.iter().enumerate() {
+        let mut paper = stages[i].clone();
+        if let Err(err) = paper.apply(instruction) {
+            println!("Fold {} failed: {}", i + 1, err);
+            return;
+        }
+        if counts {
+            println!("After {}: {} dots", instruction, paper.dots.len());
+        }
+        stages.push(paper);
+    }
+
+    let stage = stage.unwrap_or(instructions.len());
+    assert!(
+        stage <= instructions.len(),
+        "there are only {} folds",
+        instructions.len()
+    );
+    if let Some(path) = svg {
+        let fold = instructions.get(stage).copied();
+        fs::write(path, stages[stage].svg(fold)).unwrap();
+    }
+    if let Some(path) = pbm {
+        fs::write(path, stages[stage].pbm()).unwrap();
+    }
+    if let Some(path) = animation {
+        fs::write(path, animate(&stages, &instructions, 1.0)).unwrap();
+    }
+
+    match stages.get(1) {
+        Some(paper) => println!("First solution: {}", paper.dots.len()),
+        None => println!("First solution: there are no folds"),
+    }
+    let paper = stages.last().unwrap();
+    match paper.read() {
+        Ok(text) => println!("Second solution: {}", text),
+        Err(err) => println!("Second solution:\n{}{}", paper, err),
     }
-    println!("Second solution:\n{}", paper);
 }
 
-struct Paper(HashSet<(usize, usize)>);
+/// Width and height of a letter. Letters are separated by one empty column.
+const GLYPH_WIDTH: usize = 4;
+const GLYPH_HEIGHT: usize = 6;
+
+/// The letters that show up in the puzzles.
+const GLYPHS: [(char, [&str; GLYPH_HEIGHT]); 17] = [
+    ('A', [".##.", "#..#", "#..#", "####", "#..#", "#..#"]),
+    ('B', ["###.", "#..#", "###.", "#..#", "#..#", "###."]),
+    ('C', [".##.", "#..#", "#...", "#...", "#..#", ".##."]),
+    ('E', ["####", "#...", "###.", "#...", "#...", "####"]),
+    ('F', ["####", "#...", "###.", "#...", "#...", "#..."]),
+    ('G', [".##.", "#..#", "#...", "#.##", "#..#", ".###"]),
+    ('H', ["#..#", "#..#", "####", "#..#", "#..#", "#..#"]),
+    ('I', [".###", "..#.", "..#.", "..#.", "..#.", ".###"]),
+    ('J', ["..##", "...#", "...#", "...#", "#..#", ".##."]),
+    ('K', ["#..#", "#.#.", "##..", "#.#.", "#.#.", "#..#"]),
+    ('L', ["#...", "#...", "#...", "#...", "#...", "####"]),
+    ('O', [".##.", "#..#", "#..#", "#..#", "#..#", ".##."]),
+    ('P', ["###.", "#..#", "#..#", "###.", "#...", "#..."]),
+    ('R', ["###.", "#..#", "#..#", "###.", "#.#.", "#..#"]),
+    ('S', [".###", "#...", "#...", ".##.", "...#", "###."]),
+    ('U', ["#..#", "#..#", "#..#", "#..#", "#..#", ".##."]),
+    ('Z', ["####", "...#", "..#.", ".#..", "#...", "####"]),
+];
+
+/// Text with a `?` for every letter that could not be read.
+#[derive(Debug, PartialEq, Eq)]
+struct Unrecognized {
+    text: String,
+    /// Index of each unreadable letter, counted from zero.
+    positions: Vec<usize>,
+}
+
+impl Display for Unrecognized {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let positions: Vec<String> = self.positions.iter().map(|p| p.to_string()).collect();
+        write!(
+            f,
+            "read \"{}\", but could not recognize the letters at {}",
+            self.text,
+            positions.join(", ")
+        )
+    }
+}
+
+#[derive(Clone)]
+struct Paper {
+    dots: HashSet<(usize, usize)>,
+    /// The paper can be larger than the area covered by dots.
+    width: usize,
+    height: usize,
+}
 
 impl Display for Paper {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let (width, height) = self.size();
         for y in 0..=height {
             for x in 0..=width {
-                if self.0.contains(&(x, y)) {
+                if self.dots.contains(&(x, y)) {
                     write!(f, "#")?;
                 } else {
                     write!(f, " ")?;
@@ -32,48 +138,272 @@ impl Display for Paper {
 }
 
 impl Paper {
-    fn apply(&mut self, instruction: Instruction) {
-        self.0 = self
-            .0
+    fn new(dots: HashSet<(usize, usize)>) -> Self {
+        let mut paper = Self {
+            dots,
+            width: 0,
+            height: 0,
+        };
+        let (x, y) = paper.size();
+        paper.width = x + 1;
+        paper.height = y + 1;
+        paper
+    }
+
+    /// Fold the paper. If the halves are not the same size, the smaller one lands on the edge
+    /// of the bigger one that touches the fold line, and the coordinates shift so that the
+    /// result starts at zero again.
+    fn apply(&mut self, instruction: Instruction) -> Result<(), FoldError> {
+        let Instruction {
+            axis,
+            line,
+            toward_zero,
+        } = instruction;
+        let size = match axis {
+            Axis::X => self.width,
+            Axis::Y => self.height,
+        };
+        if line >= size {
+            return Err(FoldError::OutsidePaper { instruction, size });
+        }
+        if let Some(&dot) = self.dots.iter().filter(|&&dot| axis.of(dot) == line).min() {
+            return Err(FoldError::OnLine { instruction, dot });
+        }
+
+        // Measure everything by its distance to the fold line, which is the same for both halves.
+        let folded = line.max(size - line - 1);
+        let new = |c: usize| {
+            let distance = c.abs_diff(line);
+            if toward_zero {
+                folded - distance
+            } else {
+                distance - 1
+            }
+        };
+        self.dots = self
+            .dots
             .iter()
-            .copied()
-            .map(|(x, y)| match instruction {
-                Instruction::X(n) => {
-                    if x > n {
-                        (x - 2 * (x - n), y)
-                    } else {
-                        (x, y)
-                    }
-                }
-                Instruction::Y(n) => {
-                    if y > n {
-                        (x, y - 2 * (y - n))
-                    } else {
-                        (x, y)
-                    }
-                }
+            .map(|&(x, y)| match axis {
+                Axis::X => (new(x), y),
+                Axis::Y => (x, new(y)),
             })
-            .collect()
+            .collect();
+        match axis {
+            Axis::X => self.width = folded,
+            Axis::Y => self.height = folded,
+        }
+        Ok(())
+    }
+
+    /// Read the dots as a line of letters.
+    fn read(&self) -> Result<String, Unrecognized> {
+        let (width, height) = self.size();
+        let letters = (width + 1 + GLYPH_WIDTH) / (GLYPH_WIDTH + 1);
+        let mut text = String::new();
+        let mut positions = Vec::new();
+        for i in 0..letters {
+            let left = i * (GLYPH_WIDTH + 1);
+            let matches = |glyph: &[&str; GLYPH_HEIGHT]| {
+                height < GLYPH_HEIGHT
+                    && (0..GLYPH_HEIGHT).all(|y| {
+                        (0..=GLYPH_WIDTH).all(|x| {
+                            let expected = glyph[y].as_bytes().get(x) == Some(&b'#');
+                            self.dots.contains(&(left + x, y)) == expected
+                        })
+                    })
+            };
+            match GLYPHS.iter().find(|(_, glyph)| matches(glyph)) {
+                Some(&(c, _)) => text.push(c),
+                None => {
+                    text.push('?');
+                    positions.push(i);
+                }
+            }
+        }
+
+        if positions.is_empty() {
+            Ok(text)
+        } else {
+            Err(Unrecognized { text, positions })
+        }
     }
 
     /// Returns the highest x and the highest y
     fn size(&self) -> (usize, usize) {
-        self.0
+        self.dots
             .iter()
             .fold((0, 0), |(x1, y1), (x2, y2)| (x1.max(*x2), y1.max(*y2)))
     }
 }
 
-#[derive(Debug, Clone, Copy)]
-enum Instruction {
-    X(usize),
-    Y(usize),
+/// Size of a dot in pixels when drawing SVGs.
+const SVG_SCALE: usize = 4;
+
+impl Paper {
+    /// The paper as an SVG image, with the line of the next fold if there is one.
+    fn svg(&self, fold: Option<Instruction>) -> String {
+        let mut svg = svg_header(self.width, self.height);
+        svg += &self.svg_shapes(fold);
+        svg += "</svg>\n";
+        svg
+    }
+
+    /// Background, dots and fold line, without the surrounding SVG element.
+    fn svg_shapes(&self, fold: Option<Instruction>) -> String {
+        let mut shapes = format!(
+            "<rect width=\"{}\" height=\"{}\" fill=\"white\"/>\n",
+            self.width, self.height
+        );
+        let mut dots: Vec<_> = self.dots.iter().collect();
+        dots.sort_unstable();
+        for (x, y) in dots {
+            writeln!(
+                shapes,
+                "<rect x=\"{}\" y=\"{}\" width=\"1\" height=\"1\"/>",
+                x, y
+            )
+            .unwrap();
+        }
+        if let Some(Instruction { axis, line, .. }) = fold {
+            // Lines go through the middle of the row or column that is folded along.
+            let middle = line as f64 + 0.5;
+            let (x1, y1, x2, y2) = match axis {
+                Axis::X => (middle, 0.0, middle, self.height as f64),
+                Axis::Y => (0.0, middle, self.width as f64, middle),
+            };
+            writeln!(
+                shapes,
+                "<line x1=\"{}\" y1=\"{}\" x2=\"{}\" y2=\"{}\" stroke=\"red\" stroke-width=\"0.3\" stroke-dasharray=\"1\"/>",
+                x1, y1, x2, y2
+            )
+            .unwrap();
+        }
+        shapes
+    }
+
+    /// The paper as a plain PBM bitmap with one pixel per position.
+    fn pbm(&self) -> String {
+        let mut pbm = format!("P1\n{} {}\n", self.width, self.height);
+        for y in 0..self.height {
+            let row: Vec<&str> = (0..self.width)
+                .map(|x| {
+                    if self.dots.contains(&(x, y)) {
+                        "1"
+                    } else {
+                        "0"
+                    }
+                })
+                .collect();
+            pbm += &row.join(" ");
+            pbm += "\n";
+        }
+        pbm
+    }
+}
+
+fn svg_header(width: usize, height: usize) -> String {
+    format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\" viewBox=\"0 0 {} {}\">\n",
+        width * SVG_SCALE,
+        height * SVG_SCALE,
+        width,
+        height
+    )
 }
 
-fn input() -> (Paper, impl Iterator<Item = Instruction>) {
-    let file = include_str!("../../input/13.txt");
+/// An SVG that shows every stage for `seconds` with the line of the upcoming fold and starts
+/// over after the last one.
+fn animate(stages: &[Paper], folds: &[Instruction], seconds: f64) -> String {
+    let mut svg = svg_header(stages[0].width, stages[0].height);
+    for (i, stage) in stages.iter().enumerate() {
+        let begin = if i == 0 {
+            format!("0s;stage{}.end", stages.len() - 1)
+        } else {
+            format!("stage{}.end", i - 1)
+        };
+        svg += "<g visibility=\"hidden\">\n";
+        writeln!(
+            svg,
+            "<set id=\"stage{}\" attributeName=\"visibility\" to=\"visible\" begin=\"{}\" dur=\"{}s\"/>",
+            i, begin, seconds
+        )
+        .unwrap();
+        svg += &stage.svg_shapes(folds.get(i).copied());
+        svg += "</g>\n";
+    }
+    svg += "</svg>\n";
+    svg
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum FoldError {
+    /// Folds are not allowed to go through dots.
+    OnLine {
+        instruction: Instruction,
+        dot: (usize, usize),
+    },
+    /// The fold line is not on the paper, which is `size` long in that direction.
+    OutsidePaper {
+        instruction: Instruction,
+        size: usize,
+    },
+}
+
+impl Display for FoldError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FoldError::OnLine { instruction, dot } => write!(
+                f,
+                "can't {}, the dot at {},{} is on the fold line",
+                instruction, dot.0, dot.1
+            ),
+            FoldError::OutsidePaper { instruction, size } => write!(
+                f,
+                "can't {}, the paper is only {} long in that direction",
+                instruction, size
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Axis {
+    X,
+    Y,
+}
+
+impl Axis {
+    fn of(self, (x, y): (usize, usize)) -> usize {
+        match self {
+            Axis::X => x,
+            Axis::Y => y,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Instruction {
+    axis: Axis,
+    line: usize,
+    /// Fold the half with the bigger coordinates onto the other one, so up or to the left.
+    /// That's what the puzzle does, but the input may also say `fold down` or `fold right`.
+    toward_zero: bool,
+}
+
+impl Display for Instruction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (direction, axis) = match (self.axis, self.toward_zero) {
+            (Axis::X, true) => ("left", 'x'),
+            (Axis::X, false) => ("right", 'x'),
+            (Axis::Y, true) => ("up", 'y'),
+            (Axis::Y, false) => ("down", 'y'),
+        };
+        write!(f, "fold {} along {}={}", direction, axis, self.line)
+    }
+}
 
-    let paper = Paper(
+fn input(file: &str) -> (Paper, impl Iterator<Item = Instruction> + '_) {
+    let paper = Paper::new(
         file.lines()
             .filter(|l| l.contains(','))
             .map(|l| l.split_once(',').unwrap())
@@ -81,14 +411,131 @@ fn input() -> (Paper, impl Iterator<Item = Instruction>) {
             .collect(),
     );
 
-    let instructions =
-        file.lines()
-            .filter(|l| l.contains('='))
-            .map(|l| match l.split_once('=').unwrap() {
-                ("fold along x", n) => Instruction::X(n.parse().unwrap()),
-                ("fold along y", n) => Instruction::Y(n.parse().unwrap()),
-                x => panic!("unknown instruction: {:?}", x),
-            });
+    let instructions = file.lines().filter(|l| l.contains('=')).map(|l| {
+        let (words, line) = l.split_once('=').unwrap();
+        let words: Vec<&str> = words.split_whitespace().collect();
+        let (axis, toward_zero) = match words[..] {
+            ["fold", "along", "x"] | ["fold", "left", "along", "x"] => (Axis::X, true),
+            ["fold", "right", "along", "x"] => (Axis::X, false),
+            ["fold", "along", "y"] | ["fold", "up", "along", "y"] => (Axis::Y, true),
+            ["fold", "down", "along", "y"] => (Axis::Y, false),
+            _ => panic!("unknown instruction: {}", l),
+        };
+        Instruction {
+            axis,
+            line: line.parse().unwrap(),
+            toward_zero,
+        }
+    });
 
     (paper, instructions)
 }
+
+#[cfg(test)]
+mod test {
+    use crate::{input, Axis, FoldError, Instruction, Paper, Unrecognized};
+
+    fn paper(art: &str) -> Paper {
+        Paper::new(
+            art.lines()
+                .enumerate()
+                .flat_map(|(y, l)| l.match_indices('#').map(move |(x, _)| (x, y)))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn read() {
+        let art = "\
+###  #  # #  # #### ####  ##  #  # ###
+#  # # #  #  # #       # #  # #  # #  #
+#  # ##   #### ###    #  #    #  # ###
+###  # #  #  # #     #   # ## #  # #  #
+# #  # #  #  # #    #    #  # #  # #  #
+#  # #  # #  # #    ####  ###  ##  ###";
+        assert_eq!(paper(art).read(), Ok("RKHFZGUB".to_string()));
+
+        let art = "\
+#  #  #   ##
+#  #       #
+####  #    #
+#  # ###   #
+#  #  #    #
+#  # ###   #";
+        assert_eq!(
+            paper(art).read(),
+            Err(Unrecognized {
+                text: "H??".to_string(),
+                positions: vec![1, 2],
+            })
+        );
+    }
+
+    #[test]
+    fn example() {
+        let (mut paper, instructions) = input(include_str!("../../input/13-test.txt"));
+        let mut dots = Vec::new();
+        for instruction in instructions {
+            paper.apply(instruction).unwrap();
+            dots.push(paper.dots.len());
+        }
+        assert_eq!(dots, vec![17, 16]);
+        assert_eq!((paper.width, paper.height), (5, 7));
+    }
+
+    #[test]
+    fn folds() {
+        let fold = |axis, line, toward_zero| Instruction {
+            axis,
+            line,
+            toward_zero,
+        };
+
+        // The right half is longer, so the left half lands on its far end.
+        let mut p = paper("#..#....#");
+        p.apply(fold(Axis::X, 2, true)).unwrap();
+        assert_eq!(p.width, 6);
+        assert_eq!(p.to_string(), "#   ##\n\n");
+
+        let mut p = paper("#..#....#");
+        p.apply(fold(Axis::X, 2, false)).unwrap();
+        assert_eq!(p.width, 6);
+        assert_eq!(p.to_string(), "##   #\n\n");
+
+        let mut p = paper("#\n.\n#\n#");
+        p.apply(fold(Axis::Y, 1, false)).unwrap();
+        assert_eq!(p.dots, [(0, 0), (0, 1)].into_iter().collect());
+
+        let mut p = paper("#.#");
+        let instruction = fold(Axis::X, 2, true);
+        assert_eq!(
+            p.apply(instruction),
+            Err(FoldError::OnLine {
+                instruction,
+                dot: (2, 0)
+            })
+        );
+        let instruction = fold(Axis::Y, 1, true);
+        assert_eq!(
+            p.apply(instruction),
+            Err(FoldError::OutsidePaper {
+                instruction,
+                size: 1
+            })
+        );
+    }
+
+    #[test]
+    fn export() {
+        let paper = paper("#.\n.#");
+        assert_eq!(paper.pbm(), "P1\n2 2\n1 0\n0 1\n");
+        let fold = Instruction {
+            axis: Axis::Y,
+            line: 1,
+            toward_zero: true,
+        };
+        assert!(paper
+            .svg(Some(fold))
+            .contains("<line x1=\"0\" y1=\"1.5\" x2=\"2\" y2=\"1.5\""));
+    }
+}
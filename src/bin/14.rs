@@ -1,117 +1,422 @@
-use std::{collections::HashMap, hash::Hash};
+use std::{collections::HashMap, env, fmt::Display, iter};
 
 fn main() {
-    let mut puzzle = input();
+    let mut args = env::args().skip(1);
+    let mut steps = None;
+    let mut modulus = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--steps" => steps = Some(args.next().expect("missing steps").parse().unwrap()),
+            "--modulus" => modulus = Some(args.next().expect("missing modulus").parse().unwrap()),
+            err => panic!("unknown argument '{}'", err),
+        }
+    }
+
+    assert!(
+        modulus.is_none() || steps.is_some(),
+        "the solutions can't be computed modulo something, use --modulus with --steps"
+    );
+
+    let mut puzzle = input(include_str!("../../input/14.txt"));
+    if let Some(modulus) = modulus {
+        puzzle = puzzle.with_modulus(modulus);
+    }
+
+    if let Some(steps) = steps {
+        match puzzle.histogram_after(steps) {
+            Ok(histogram) => {
+                let mut histogram: Vec<_> = histogram.into_iter().collect();
+                histogram.sort_unstable();
+                for (element, count) in histogram {
+                    println!("{}: {}", element, count);
+                }
+            }
+            Err(err) => println!("{}", err),
+        }
+        return;
+    }
+
     for _ in 0..10 {
-        puzzle.step();
+        puzzle.step().unwrap();
     }
-    println!("First solution: {}", puzzle.score());
+    println!("First solution: {}", puzzle.score().unwrap());
     for _ in 0..30 {
-        puzzle.step();
+        puzzle.step().unwrap();
     }
-    println!("Second solution: {}", puzzle.score());
+    println!("Second solution: {}", puzzle.score().unwrap());
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
-struct Digram(char, char);
-
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 struct Puzzle {
-    /// polymer is a count of all digrams in it.
-    polymer: HashMap<Digram, usize>,
-    /// start and end need to be kept track of, since all other chars are counted doubly,
-    /// due to the nature of counting the digrams.
-    start: char,
-    end: char,
-    instructions: Vec<Instruction>,
+    /// All elements, so that they can be referred to by their index.
+    elements: Vec<char>,
+    /// polymer is a count of all digrams in it, by digram index. The digram of the elements `a`
+    /// and `b` has the index `a * elements.len() + b`.
+    polymer: Vec<u128>,
+    /// The element that is inserted into each digram, by digram index.
+    rules: Vec<Option<usize>>,
+    /// Every element is the first one of a digram, except the one at the end. That one needs to
+    /// be kept track of to count the elements.
+    end: usize,
+    /// Number of steps done so far.
+    steps: usize,
+    /// Keep all counts modulo this instead of failing when they get too big.
+    modulus: Option<u128>,
+}
+
+/// The counts got too big for a `u128` in this step. When jumping ahead, the step is the one
+/// that was jumped to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Overflow {
+    step: usize,
+}
+
+impl Display for Overflow {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "counts overflow in step {}, try a modulus", self.step)
+    }
 }
 
-/// ab -> to
-#[derive(Debug, Clone, Copy)]
-struct Instruction {
-    from: Digram,
-    to: char,
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ScoreError {
+    Overflow(Overflow),
+    /// The counts are only known modulo something.
+    Modulus,
+}
+
+impl From<Overflow> for ScoreError {
+    fn from(overflow: Overflow) -> Self {
+        Self::Overflow(overflow)
+    }
 }
 
 impl Puzzle {
-    fn step(&mut self) {
-        let mut new_polymer = HashMap::new();
-        for (&old_digram, &count) in &self.polymer {
-            if let Some(i) = self.find_instruction(old_digram) {
-                for new_digram in i.output() {
-                    let counter = new_polymer.entry(new_digram).or_default();
-                    *counter += count;
-                }
-            } else {
-                let counter = new_polymer.entry(old_digram).or_default();
-                *counter += count;
+    fn with_modulus(mut self, modulus: u128) -> Self {
+        assert!(modulus > 0, "modulus must be positive");
+        for count in &mut self.polymer {
+            *count %= modulus;
+        }
+        self.modulus = Some(modulus);
+        self
+    }
+
+    fn digram(&self, a: usize, b: usize) -> usize {
+        a * self.elements.len() + b
+    }
+
+    /// The elements of a digram.
+    fn split(&self, digram: usize) -> (usize, usize) {
+        (digram / self.elements.len(), digram % self.elements.len())
+    }
+
+    /// The digrams that a digram turns into in one step.
+    fn successors(&self, digram: usize) -> impl Iterator<Item = usize> {
+        let (a, b) = self.split(digram);
+        let (first, second) = match self.rules[digram] {
+            Some(inserted) => (self.digram(a, inserted), Some(self.digram(inserted, b))),
+            None => (digram, None),
+        };
+        iter::once(first).chain(second)
+    }
+
+    /// Every digram has at most two successors, so this is a sparse matrix-vector product.
+    fn step(&mut self) -> Result<(), Overflow> {
+        self.steps += 1;
+        let overflow = Overflow { step: self.steps };
+        let mut new_polymer = vec![0; self.polymer.len()];
+        for (digram, &count) in self.polymer.iter().enumerate() {
+            if count == 0 {
+                continue;
+            }
+            for successor in self.successors(digram) {
+                let counter = &mut new_polymer[successor];
+                *counter = add(*counter, count, self.modulus).ok_or(overflow)?;
             }
         }
         self.polymer = new_polymer;
+        Ok(())
+    }
+
+    /// Skip ahead by raising the transition matrix to the power of `steps`. That takes
+    /// logarithmic time in the number of steps, but each multiplication is cubic in the number
+    /// of digrams.
+    fn jump(&mut self, steps: usize) -> Result<(), Overflow> {
+        let overflow = Overflow {
+            step: self.steps + steps,
+        };
+        let matrix = self.transition().pow(steps, self.modulus).ok_or(overflow)?;
+        self.polymer = matrix.apply(&self.polymer, self.modulus).ok_or(overflow)?;
+        self.steps += steps;
+        Ok(())
     }
 
-    fn find_instruction(&self, d: Digram) -> Option<Instruction> {
-        self.instructions.iter().copied().find(|i| i.from == d)
+    /// The matrix that does one step when multiplied with the polymer.
+    fn transition(&self) -> Matrix {
+        let mut matrix = Matrix::zero(self.polymer.len());
+        for from in 0..self.polymer.len() {
+            for to in self.successors(from) {
+                matrix.cells[to * matrix.size + from] += 1;
+            }
+        }
+        matrix
     }
 
-    fn score(&self) -> usize {
-        // All chars were counted doubly, due to the nature of the digrams, except start and end.
-        // Add one for those manually here.
-        let mut char_count: HashMap<char, usize> =
-            [(self.start, 1), (self.end, 1)].into_iter().collect();
+    /// How often each element occurs in the polymer.
+    fn histogram(&self) -> Result<HashMap<char, u128>, Overflow> {
+        let overflow = Overflow { step: self.steps };
+        let mut histogram = vec![0; self.elements.len()];
+        histogram[self.end] = add(0, 1, self.modulus).ok_or(overflow)?;
+        for (digram, &count) in self.polymer.iter().enumerate() {
+            let counter = &mut histogram[self.split(digram).0];
+            *counter = add(*counter, count, self.modulus).ok_or(overflow)?;
+        }
+        Ok(self.elements.iter().copied().zip(histogram).collect())
+    }
 
-        for (&d, &count) in &self.polymer {
-            for c in [d.0, d.1] {
-                let counter = char_count.entry(c).or_default();
-                *counter += count;
+    /// How often each element occurs after a number of further steps.
+    fn histogram_after(&self, steps: usize) -> Result<HashMap<char, u128>, Overflow> {
+        let mut puzzle = self.clone();
+        // One step is about as expensive as one matrix-vector product, so stepping wins until
+        // the number of steps gets close to the number of matrix entries.
+        if steps < self.polymer.len() * self.polymer.len() {
+            for _ in 0..steps {
+                puzzle.step()?;
             }
+        } else {
+            puzzle.jump(steps)?;
         }
+        puzzle.histogram()
+    }
 
-        let mut doubly_counted: Vec<usize> = char_count.values().copied().collect();
-        doubly_counted.sort_unstable();
+    /// Count of the most common element minus the count of the least common one. Counts modulo
+    /// something can't be compared, so this fails with a modulus.
+    fn score(&self) -> Result<u128, ScoreError> {
+        if self.modulus.is_some() {
+            return Err(ScoreError::Modulus);
+        }
+        // Without a modulus, the last element is always counted, so there is a max and a min.
+        let histogram = self.histogram()?;
+        let counts = histogram.values().filter(|&&c| c > 0);
+        let max = counts.clone().max().unwrap();
+        let min = counts.min().unwrap();
+        Ok(max - min)
+    }
+}
 
-        (doubly_counted.last().unwrap() - doubly_counted.first().unwrap()) / 2
+/// Square matrix in row-major order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Matrix {
+    size: usize,
+    cells: Vec<u128>,
+}
+
+impl Matrix {
+    fn zero(size: usize) -> Self {
+        Self {
+            size,
+            cells: vec![0; size * size],
+        }
+    }
+
+    fn identity(size: usize) -> Self {
+        let mut matrix = Self::zero(size);
+        for i in 0..size {
+            matrix.cells[i * size + i] = 1;
+        }
+        matrix
+    }
+
+    /// `None` if a cell overflows.
+    fn mul(&self, other: &Matrix, modulus: Option<u128>) -> Option<Matrix> {
+        let n = self.size;
+        let mut result = Matrix::zero(n);
+        for row in 0..n {
+            for k in 0..n {
+                let a = self.cells[row * n + k];
+                if a == 0 {
+                    continue;
+                }
+                for col in 0..n {
+                    let b = other.cells[k * n + col];
+                    let cell = &mut result.cells[row * n + col];
+                    *cell = add(*cell, mul(a, b, modulus)?, modulus)?;
+                }
+            }
+        }
+        Some(result)
+    }
+
+    fn pow(&self, mut exponent: usize, modulus: Option<u128>) -> Option<Matrix> {
+        let mut result = Matrix::identity(self.size);
+        if let Some(m) = modulus {
+            for cell in &mut result.cells {
+                *cell %= m;
+            }
+        }
+        let mut base = self.clone();
+        while exponent > 0 {
+            if exponent % 2 == 1 {
+                result = result.mul(&base, modulus)?;
+            }
+            exponent /= 2;
+            if exponent > 0 {
+                base = base.mul(&base, modulus)?;
+            }
+        }
+        Some(result)
+    }
+
+    fn apply(&self, vector: &[u128], modulus: Option<u128>) -> Option<Vec<u128>> {
+        let n = self.size;
+        (0..n)
+            .map(|row| {
+                (0..n).try_fold(0, |sum, col| {
+                    add(
+                        sum,
+                        mul(self.cells[row * n + col], vector[col], modulus)?,
+                        modulus,
+                    )
+                })
+            })
+            .collect()
     }
 }
 
-impl Instruction {
-    fn output(&self) -> [Digram; 2] {
-        [Digram(self.from.0, self.to), Digram(self.to, self.from.1)]
+/// Add two counts. With a modulus, both need to be below it already. `None` on overflow.
+fn add(a: u128, b: u128, modulus: Option<u128>) -> Option<u128> {
+    match modulus {
+        // Neither of these can overflow.
+        Some(m) if a >= m - b => Some(a - (m - b)),
+        Some(_) => Some(a + b),
+        None => a.checked_add(b),
     }
 }
 
-fn input() -> Puzzle {
-    let file = include_str!("../../input/14.txt");
+/// Multiply two counts. With a modulus, both need to be below it already. `None` on overflow.
+fn mul(a: u128, b: u128, modulus: Option<u128>) -> Option<u128> {
+    match modulus {
+        Some(m) => Some(match a.checked_mul(b) {
+            Some(product) => product % m,
+            // Double and add, which never leaves the range of the modulus.
+            None => (0..128).rev().fold(0, |result, bit| {
+                let doubled = add(result, result, modulus).unwrap();
+                if b >> bit & 1 == 1 {
+                    add(doubled, a, modulus).unwrap()
+                } else {
+                    doubled
+                }
+            }),
+        }),
+        None => a.checked_mul(b),
+    }
+}
 
-    let polymer_chars: Vec<char> = file.lines().next().unwrap().trim().chars().collect();
-    let polymer = polymer_chars
-        .windows(2)
-        .into_iter()
-        .map(|w| Digram(w[0], w[1]))
-        .fold(HashMap::new(), |mut acc, d| {
-            let count = acc.entry(d).or_default();
-            *count += 1;
-            acc
-        });
-
-    let instructions = file
+fn input(file: &str) -> Puzzle {
+    let template: Vec<char> = file.lines().next().unwrap().trim().chars().collect();
+    let rules: Vec<(char, char, char)> = file
         .lines()
-        .filter(|l| l.contains("->"))
         .filter_map(|l| l.split_once(" -> "))
         .map(|(from, to)| {
-            let a = from.chars().next().unwrap();
-            let b = from.chars().nth(1).unwrap();
-            Instruction {
-                from: Digram(a, b),
-                to: to.chars().next().unwrap(),
-            }
+            let mut from = from.chars();
+            let a = from.next().unwrap();
+            let b = from.next().unwrap();
+            (a, b, to.chars().next().unwrap())
         })
         .collect();
 
-    Puzzle {
-        polymer,
-        instructions,
-        start: polymer_chars.first().copied().unwrap(),
-        end: polymer_chars.last().copied().unwrap(),
+    let mut elements: Vec<char> = template
+        .iter()
+        .copied()
+        .chain(rules.iter().flat_map(|&(a, b, c)| [a, b, c]))
+        .collect();
+    elements.sort_unstable();
+    elements.dedup();
+    let index = |c: char| elements.binary_search(&c).unwrap();
+    let template: Vec<usize> = template.into_iter().map(index).collect();
+    let rules: Vec<(usize, usize, usize)> = rules
+        .into_iter()
+        .map(|(a, b, inserted)| (index(a), index(b), index(inserted)))
+        .collect();
+
+    let n = elements.len();
+    let mut puzzle = Puzzle {
+        polymer: vec![0; n * n],
+        rules: vec![None; n * n],
+        end: *template.last().unwrap(),
+        steps: 0,
+        modulus: None,
+        elements,
+    };
+    for w in template.windows(2) {
+        let digram = puzzle.digram(w[0], w[1]);
+        puzzle.polymer[digram] += 1;
+    }
+    for (a, b, inserted) in rules {
+        let digram = puzzle.digram(a, b);
+        puzzle.rules[digram] = Some(inserted);
+    }
+    puzzle
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{input, Overflow, ScoreError};
+
+    #[test]
+    fn example() {
+        let puzzle = input(include_str!("../../input/14-test.txt"));
+        let histogram = puzzle.histogram_after(10).unwrap();
+        assert_eq!(histogram[&'B'], 1749);
+        assert_eq!(histogram[&'C'], 298);
+        assert_eq!(histogram[&'H'], 161);
+        assert_eq!(histogram[&'N'], 865);
+
+        let mut puzzle = puzzle;
+        for _ in 0..40 {
+            puzzle.step().unwrap();
+        }
+        assert_eq!(puzzle.score(), Ok(2188189693529));
+    }
+
+    #[test]
+    fn overflow() {
+        let puzzle = input(include_str!("../../input/14-test.txt"));
+        let Err(Overflow { step }) = puzzle.histogram_after(200) else {
+            panic!("200 steps should overflow");
+        };
+        assert!(step > 100);
+
+        let modulus = 1_000_000_007;
+        let exact = puzzle.histogram_after(50).unwrap();
+        let reduced = puzzle.clone().with_modulus(modulus).histogram_after(50);
+        for (element, count) in reduced.unwrap() {
+            assert_eq!(count, exact[&element] % modulus);
+        }
+        let puzzle = puzzle.with_modulus(modulus);
+        assert!(puzzle.histogram_after(200).is_ok());
+        assert_eq!(puzzle.score(), Err(ScoreError::Modulus));
+        // Everything is zero modulo one.
+        assert_eq!(puzzle.with_modulus(1).score(), Err(ScoreError::Modulus));
+    }
+
+    #[test]
+    fn jump() {
+        let puzzle = input(include_str!("../../input/14-test.txt"));
+        let mut stepped = puzzle.clone();
+        for _ in 0..40 {
+            stepped.step().unwrap();
+        }
+        let mut jumped = puzzle.clone();
+        jumped.jump(40).unwrap();
+        assert_eq!(jumped.polymer, stepped.polymer);
+
+        let modulus = u128::MAX - 158;
+        let mut stepped = puzzle.clone().with_modulus(modulus);
+        for _ in 0..300 {
+            stepped.step().unwrap();
+        }
+        let mut jumped = puzzle.with_modulus(modulus);
+        jumped.jump(300).unwrap();
+        assert_eq!(jumped.polymer, stepped.polymer);
     }
 }
@@ -1,11 +1,63 @@
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashSet};
+use std::env;
+use std::fs;
 use std::ops::{Index, IndexMut};
 
 fn main() {
-    let mut puzzle = input();
-    println!("First solution: {}", puzzle.solve());
-    let mut puzzle = input();
+    let mut args = env::args().skip(1);
+    let mut search = Search::Dijkstra;
+    let mut expanded = false;
+    let (mut from, mut to) = (None, None);
+    let mut show = false;
+    let mut ppm = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--astar" => search = Search::AStar,
+            "--expanded" => expanded = true,
+            "--from" => from = Some(position(args.next())),
+            "--to" => to = Some(position(args.next())),
+            "--path" => show = true,
+            "--ppm" => ppm = Some(args.next().expect("missing output file")),
+            err => panic!("unknown argument '{}'", err),
+        }
+    }
+
+    let mut puzzle = input(include_str!("../../input/15.txt"));
+    if from.is_some() || to.is_some() || show || ppm.is_some() {
+        if expanded {
+            puzzle.expand();
+        }
+        let from = from.unwrap_or((0, 0));
+        let to = to.unwrap_or_else(|| puzzle.corner());
+        let route = puzzle.solve(from, to, search);
+        let path = route.as_ref().map_or(&[][..], |r| &r.path);
+        if show {
+            print!("{}", puzzle.ansi(path));
+        }
+        if let Some(file) = ppm {
+            // The search stops at the goal, so it doesn't know all distances.
+            puzzle.distances(from);
+            fs::write(file, puzzle.ppm(path, 4)).unwrap();
+        }
+        match route {
+            Some(route) => println!("Lowest risk: {}", route.risk),
+            None => println!("There is no path"),
+        }
+        return;
+    }
+
+    let solve = |puzzle: &mut Puzzle| puzzle.solve((0, 0), puzzle.corner(), search).unwrap();
+    println!("First solution: {}", solve(&mut puzzle).risk);
     puzzle.expand();
-    println!("Second solution: {}", puzzle.solve());
+    println!("Second solution: {}", solve(&mut puzzle).risk);
+}
+
+/// Parse `row,col`.
+fn position(arg: Option<String>) -> Position {
+    let arg = arg.expect("missing position");
+    let (row, col) = arg.split_once(',').expect("positions look like row,col");
+    (row.parse().unwrap(), col.parse().unwrap())
 }
 
 type Position = (usize, usize);
@@ -14,6 +66,8 @@ type Position = (usize, usize);
 struct Node {
     visited: bool,
     distance: u32,
+    /// The node before this one on the shortest path found so far.
+    previous: Option<Position>,
     value: u32,
 }
 
@@ -22,50 +76,109 @@ impl From<u32> for Node {
         Self {
             visited: false,
             distance: u32::MAX,
+            previous: None,
             value: v,
         }
     }
 }
 
+/// The lowest-risk way from one position to another.
+#[derive(Debug, PartialEq, Eq)]
+struct Route {
+    risk: u32,
+    /// All positions from the start to the goal, including both.
+    path: Vec<Position>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Search {
+    Dijkstra,
+    /// Dijkstra that prefers nodes closer to the goal, by their Manhattan distance to it.
+    AStar,
+}
+
 #[derive(Debug)]
 struct Puzzle(Vec<Vec<Node>>);
 
 impl Puzzle {
-    /// Solve the puzzle with Dijsktra
-    fn solve(&mut self) -> u32 {
-        let mut stack: Vec<Position> = [(0, 0)].into_iter().collect();
-        self[(0, 0)].distance = 0;
-
-        while !stack.is_empty() {
-            // Pop position with smallest distance, we will work on that node next.
-            let mut smallest_idx = 0;
-            let mut smallest_dist = u32::MAX;
-            for (i, p) in stack.iter().enumerate() {
-                if self[*p].distance < smallest_dist {
-                    smallest_dist = self[*p].distance;
-                    smallest_idx = i;
-                }
+    /// Lowest-risk route from `start` to `goal`. The risk of the start doesn't count.
+    fn solve(&mut self, start: Position, goal: Position, search: Search) -> Option<Route> {
+        assert!(self.contains(goal), "goal must be on the map");
+        self.explore(start, Some(goal), search);
+        self[goal].visited.then(|| Route {
+            risk: self[goal].distance,
+            path: self.path_to(goal),
+        })
+    }
+
+    /// Let every reachable node know its distance from `start`.
+    fn distances(&mut self, start: Position) {
+        self.explore(start, None, Search::Dijkstra);
+    }
+
+    /// Visit nodes in the order of their distance from `start`, until `goal` is reached or
+    /// everything has been visited. Afterwards, visited nodes know their distance and the node
+    /// before them on the way there.
+    fn explore(&mut self, start: Position, goal: Option<Position>, search: Search) {
+        assert!(self.contains(start), "start must be on the map");
+        for node in self.0.iter_mut().flatten() {
+            node.visited = false;
+            node.distance = u32::MAX;
+            node.previous = None;
+        }
+
+        // Every step costs at least the lowest risk, so this never overestimates.
+        let lowest = self.0.iter().flatten().map(|n| n.value).min().unwrap();
+        let heuristic = |p: Position| match (search, goal) {
+            (Search::AStar, Some(goal)) => {
+                (p.0.abs_diff(goal.0) + p.1.abs_diff(goal.1)) as u32 * lowest
             }
+            _ => 0,
+        };
 
-            let pos = stack.remove(smallest_idx);
+        let mut queue = BinaryHeap::new();
+        self[start].distance = 0;
+        queue.push(Reverse((heuristic(start), start)));
 
+        while let Some(Reverse((_, pos))) = queue.pop() {
+            // Nodes are pushed again whenever a shorter way to them is found, the old entries
+            // are skipped here.
             if self[pos].visited {
                 continue;
             } else {
                 self[pos].visited = true;
             }
+            if Some(pos) == goal {
+                return;
+            }
 
-            // Update neighbors and add them to the stack
+            // Update neighbors and add them to the queue
             for next in self.neighbors(pos) {
                 let new_dist = self[next].value + self[pos].distance;
                 if new_dist < self[next].distance {
                     self[next].distance = new_dist;
+                    self[next].previous = Some(pos);
+                    queue.push(Reverse((new_dist + heuristic(next), next)));
                 }
-                stack.push(next);
             }
         }
+    }
+
+    /// Follow the previous nodes back to the start.
+    fn path_to(&self, goal: Position) -> Vec<Position> {
+        let mut path: Vec<Position> =
+            std::iter::successors(Some(goal), |&p| self[p].previous).collect();
+        path.reverse();
+        path
+    }
+
+    fn contains(&self, p: Position) -> bool {
+        p.0 < self.0.len() && p.1 < self.0[0].len()
+    }
 
-        self.0.last().unwrap().last().unwrap().distance
+    /// The bottom right corner.
+    fn corner(&self) -> Position {
+        (self.0.len() - 1, self.0[0].len() - 1)
     }
 
     /// Only returns unvisited neighbors
@@ -113,6 +226,59 @@ impl Puzzle {
     }
 }
 
+impl Puzzle {
+    /// The risk levels with the path highlighted.
+    fn ansi(&self, path: &[Position]) -> String {
+        let path: HashSet<_> = path.iter().collect();
+        let mut result = String::new();
+        for (row, line) in self.0.iter().enumerate() {
+            for (col, node) in line.iter().enumerate() {
+                if path.contains(&(row, col)) {
+                    result += &format!("\x1b[1;33m{}", node.value);
+                } else {
+                    result += &format!("\x1b[0;90m{}", node.value);
+                }
+            }
+            result += "\x1b[0m\n";
+        }
+        result
+    }
+
+    /// Heat map of the distances from `distances`, from blue at the start to red far away, with
+    /// the path in white on top. Nodes that can't be reached are dark gray.
+    fn ppm(&self, path: &[Position], scale: usize) -> Vec<u8> {
+        let path: HashSet<_> = path.iter().collect();
+        let (rows, cols) = (self.0.len(), self.0[0].len());
+        let farthest = self
+            .0
+            .iter()
+            .flatten()
+            .filter(|n| n.visited)
+            .map(|n| n.distance)
+            .max()
+            .unwrap_or(0)
+            .max(1);
+
+        let mut result = format!("P6\n{} {}\n255\n", cols * scale, rows * scale).into_bytes();
+        for y in 0..rows * scale {
+            for x in 0..cols * scale {
+                let position = (y / scale, x / scale);
+                let node = &self[position];
+                let color = if path.contains(&position) {
+                    [255, 255, 255]
+                } else if node.visited {
+                    let t = node.distance as f64 / farthest as f64;
+                    [(255.0 * t) as u8, 40, (255.0 * (1.0 - t)) as u8]
+                } else {
+                    [40, 40, 40]
+                };
+                result.extend(color);
+            }
+        }
+        result
+    }
+}
+
 impl Index<Position> for Puzzle {
     type Output = Node;
 
@@ -127,10 +293,9 @@ impl IndexMut<Position> for Puzzle {
     }
 }
 
-fn input() -> Puzzle {
+fn input(s: &str) -> Puzzle {
     Puzzle(
-        include_str!("../../input/15.txt")
-            .lines()
+        s.lines()
             .map(|l| {
                 l.chars()
                     .map(|c| c.to_digit(10).unwrap())
@@ -140,3 +305,64 @@ fn input() -> Puzzle {
             .collect(),
     )
 }
+
+#[cfg(test)]
+mod test {
+    use crate::{input, Route, Search};
+
+    #[test]
+    fn example() {
+        for search in [Search::Dijkstra, Search::AStar] {
+            let mut puzzle = input(include_str!("../../input/15-test.txt"));
+            let route = puzzle.solve((0, 0), puzzle.corner(), search).unwrap();
+            assert_eq!(route.risk, 40);
+            let risk: u32 = route.path[1..].iter().map(|&p| puzzle[p].value).sum();
+            assert_eq!(risk, 40);
+            puzzle.expand();
+            assert_eq!(
+                puzzle.solve((0, 0), puzzle.corner(), search).unwrap().risk,
+                315
+            );
+        }
+    }
+
+    #[test]
+    fn anywhere() {
+        let mut puzzle = input("191\n191\n111");
+        for search in [Search::Dijkstra, Search::AStar] {
+            assert_eq!(puzzle.solve((0, 0), (0, 2), search).unwrap().risk, 6);
+            assert_eq!(puzzle.solve((0, 2), (0, 0), search).unwrap().risk, 6);
+            assert_eq!(puzzle.solve((1, 1), (0, 1), search).unwrap().risk, 9);
+            assert_eq!(puzzle.solve((2, 2), (2, 2), search).unwrap().risk, 0);
+        }
+    }
+
+    #[test]
+    fn path() {
+        let mut puzzle = input("191\n191\n111");
+        assert_eq!(
+            puzzle.solve((0, 0), (0, 2), Search::AStar),
+            Some(Route {
+                risk: 6,
+                path: vec![(0, 0), (1, 0), (2, 0), (2, 1), (2, 2), (1, 2), (0, 2)],
+            })
+        );
+    }
+
+    #[test]
+    fn ppm() {
+        let mut puzzle = input("191\n191\n111");
+        let route = puzzle.solve((0, 0), (0, 2), Search::AStar).unwrap();
+        puzzle.distances((0, 0));
+        let ppm = puzzle.ppm(&route.path, 1);
+
+        let pixels: Vec<&[u8]> = ppm[b"P6\n3 3\n255\n".len()..].chunks(3).collect();
+        // The path goes around the middle, which is the farthest node.
+        assert_eq!(pixels[0], [255, 255, 255]);
+        assert_eq!(pixels[2], [255, 255, 255]);
+        assert_eq!(pixels[4], [255, 40, 0]);
+        // (0, 1) is 9 away, (1, 1) is 10.
+        assert_eq!(pixels[1], [229, 40, 25]);
+        assert!(pixels.iter().all(|&p| p != [40, 40, 40]));
+    }
+}